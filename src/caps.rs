@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{sasl, send, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub static DEFAULT_CAPS: &[&str] = &[
+    "message-tags",
+    "server-time",
+    "account-notify",
+    "account-tag",
+    "extended-join",
+    "away-notify",
+    "multi-prefix",
+    "echo-message",
+];
+
+#[derive(Debug)]
+pub struct Capabilities {
+    wanted: Vec<String>,
+    available: HashMap<String, String>,
+    enabled: HashSet<String>,
+    negotiating: bool,
+}
+
+impl Capabilities {
+    pub fn new(wanted: Vec<String>) -> Capabilities {
+        return Capabilities {
+            wanted: wanted,
+            available: HashMap::new(),
+            enabled: HashSet::new(),
+            negotiating: false,
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.available.clear();
+        self.enabled.clear();
+        self.negotiating = false;
+    }
+
+    pub fn want(&mut self, cap: &str) {
+        if !self.wanted.iter().any(|c| c == cap) {
+            self.wanted.push(String::from(cap));
+        }
+    }
+
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        return self.enabled.contains(cap);
+    }
+
+    pub fn finish(&mut self) {
+        self.negotiating = false;
+    }
+
+    // caps we want that the server offers and we don't have yet
+    fn to_request(&self) -> Vec<String> {
+        return self
+            .wanted
+            .iter()
+            .filter(|cap| self.available.contains_key(*cap) && !self.enabled.contains(*cap))
+            .cloned()
+            .collect();
+    }
+
+    fn add_available(&mut self, caps: &str) {
+        for cap in caps.split_whitespace() {
+            let mut parts = cap.splitn(2, "=");
+            let name = String::from(parts.next().unwrap_or(""));
+            let value = String::from(parts.next().unwrap_or(""));
+            self.available.insert(name, value);
+        }
+    }
+
+    fn acknowledge(&mut self, caps: &str) {
+        for cap in caps.split_whitespace() {
+            if cap.starts_with("-") {
+                self.enabled.remove(&cap[1..]);
+            } else {
+                self.enabled.insert(String::from(cap));
+            }
+        }
+    }
+}
+
+pub fn begin(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    bot.caps.reset();
+    bot.caps.negotiating = true;
+    send(stream, &String::from("CAP LS 302"))?;
+    Ok(())
+}
+
+fn request(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    let caps = bot.caps.to_request();
+    if caps.len() > 0 {
+        send(stream, &format!("CAP REQ :{}", caps.join(" ")))?;
+    } else {
+        negotiated(bot, stream)?;
+    }
+    Ok(())
+}
+
+// with sasl configured we log in before CAP END, or don't register at all
+fn negotiated(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    if !bot.caps.negotiating || bot.sasl.is_none() {
+        return end(bot, stream);
+    }
+    if bot.has_cap("sasl") {
+        return sasl::start(bot, stream);
+    }
+    sasl::fail(bot, stream, "the server didn't enable sasl")
+}
+
+pub fn end(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    if bot.caps.negotiating {
+        bot.caps.negotiating = false;
+        log::info!("capabilities enabled: {:?}", bot.caps.enabled);
+        send(stream, &String::from("CAP END"))?;
+    }
+    Ok(())
+}
+
+// CAP <target> <subcommand> [*] :<caps>
+pub fn on_cap(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if msg.args.len() < 3 {
+        return Ok(());
+    }
+
+    let subcommand = msg.args[1].as_str();
+    let more = msg.args.len() > 3 && msg.args[2] == "*";
+    let caps = msg.args.last().unwrap();
+
+    match subcommand {
+        "LS" => {
+            bot.caps.add_available(caps);
+            if !more && bot.caps.negotiating {
+                request(bot, stream)?;
+            }
+        }
+        "NEW" => {
+            bot.caps.add_available(caps);
+            request(bot, stream)?;
+        }
+        "DEL" => {
+            for cap in caps.split_whitespace() {
+                bot.caps.available.remove(cap);
+                bot.caps.enabled.remove(cap);
+            }
+        }
+        "ACK" => {
+            bot.caps.acknowledge(caps);
+            negotiated(bot, stream)?;
+        }
+        "NAK" => {
+            log::warn!("capabilities rejected: {}", caps);
+            negotiated(bot, stream)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconnect::Disconnect;
+    use crate::sasl::Mechanism;
+    use crate::testutil::{bot, connection, message, sent};
+
+    fn cap(bot: &mut IrcBot, stream: &mut IrcConnection, line: &str) {
+        on_cap(bot, stream, &message(line)).unwrap();
+    }
+
+    fn plain() -> Mechanism {
+        return Mechanism::Plain {
+            account: String::from("rusty"),
+            password: String::from("hunter2"),
+        };
+    }
+
+    #[test]
+    fn waits_for_the_last_ls_line() {
+        let mut bot = bot();
+        bot.set_caps(vec![String::from("message-tags"), String::from("multi-prefix")]);
+        let (mut stream, mut lines) = connection();
+
+        begin(&mut bot, &mut stream).unwrap();
+        assert_eq!(sent(&mut lines), vec!["CAP LS 302"]);
+        cap(&mut bot, &mut stream, ":server CAP * LS * :message-tags server-time");
+        assert_eq!(sent(&mut lines), Vec::<String>::new());
+        cap(&mut bot, &mut stream, ":server CAP * LS :sasl=PLAIN multi-prefix");
+        assert_eq!(sent(&mut lines), vec!["CAP REQ :message-tags multi-prefix"]);
+
+        cap(&mut bot, &mut stream, ":server CAP * ACK :message-tags multi-prefix");
+        assert_eq!(sent(&mut lines), vec!["CAP END"]);
+        assert!(bot.has_cap("message-tags") && bot.has_cap("multi-prefix"));
+    }
+
+    #[test]
+    fn ends_when_nothing_is_wanted() {
+        let mut bot = bot();
+        let (mut stream, mut lines) = connection();
+        begin(&mut bot, &mut stream).unwrap();
+        cap(&mut bot, &mut stream, ":server CAP * LS :something-else");
+        assert_eq!(sent(&mut lines), vec!["CAP LS 302", "CAP END"]);
+    }
+
+    #[test]
+    fn ends_after_a_nak() {
+        let mut bot = bot();
+        bot.set_caps(vec![String::from("message-tags")]);
+        let (mut stream, mut lines) = connection();
+        begin(&mut bot, &mut stream).unwrap();
+        cap(&mut bot, &mut stream, ":server CAP * LS :message-tags");
+        cap(&mut bot, &mut stream, ":server CAP * NAK :message-tags");
+        assert_eq!(sent(&mut lines), vec!["CAP LS 302", "CAP REQ :message-tags", "CAP END"]);
+        assert!(!bot.has_cap("message-tags"));
+    }
+
+    #[test]
+    fn starts_sasl_before_ending() {
+        let mut bot = bot();
+        bot.set_caps(Vec::new());
+        bot.set_sasl(plain());
+        let (mut stream, mut lines) = connection();
+        begin(&mut bot, &mut stream).unwrap();
+        cap(&mut bot, &mut stream, ":server CAP * LS :sasl=PLAIN,EXTERNAL");
+        cap(&mut bot, &mut stream, ":server CAP * ACK :sasl");
+        assert_eq!(sent(&mut lines), vec!["CAP LS 302", "CAP REQ :sasl", "AUTHENTICATE PLAIN"]);
+        assert!(bot.abort.is_none());
+    }
+
+    #[test]
+    fn quits_when_sasl_is_refused() {
+        let mut bot = bot();
+        bot.set_caps(Vec::new());
+        bot.set_sasl(plain());
+        let (mut stream, mut lines) = connection();
+        begin(&mut bot, &mut stream).unwrap();
+        cap(&mut bot, &mut stream, ":server CAP * LS :sasl");
+        cap(&mut bot, &mut stream, ":server CAP * NAK :sasl");
+        assert_eq!(
+            sent(&mut lines),
+            vec!["CAP LS 302", "CAP REQ :sasl", "QUIT :sasl authentication failed"]
+        );
+        assert!(matches!(bot.abort, Some(Disconnect::Auth(_))));
+        assert!(!bot.fatal);
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::Duration;
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_greet_cooldown() -> i64 {
+    300
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    pub name: String,
+    pub key: Option<String>,
+    // None enables every command
+    pub commands: Option<Vec<String>>,
+    #[serde(default = "default_true")]
+    pub reposts: bool,
+    #[serde(default = "default_true")]
+    pub greetings: bool,
+    // seconds
+    #[serde(default = "default_greet_cooldown")]
+    pub greet_cooldown: i64,
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "on" | "yes" | "true" => Ok(true),
+        "off" | "no" | "false" => Ok(false),
+        _ => Err(Box::new(Error::new(&format!("invalid value for {}: {}", key, value)))),
+    }
+}
+
+impl ChannelConfig {
+    pub fn new(name: &str) -> ChannelConfig {
+        return ChannelConfig {
+            name: String::from(name),
+            key: None,
+            commands: None,
+            reposts: true,
+            greetings: true,
+            greet_cooldown: default_greet_cooldown(),
+        };
+    }
+
+    // #chan[,key=secret][,commands=ud+weather][,reposts=off][,greetings=off][,greet_cooldown=300]
+    pub fn parse(spec: &str) -> Result<ChannelConfig> {
+        let mut parts = spec.split(",");
+        let mut channel = ChannelConfig::new(parts.next().unwrap_or(""));
+        if channel.name.len() == 0 {
+            return Err(Box::new(Error::new(&format!("missing channel name: {}", spec))));
+        }
+
+        for part in parts {
+            let mut option = part.splitn(2, "=");
+            let key = option.next().unwrap_or("");
+            let value = option.next().unwrap_or("");
+            match key {
+                "key" => channel.key = Some(String::from(value)),
+                "commands" => {
+                    channel.commands = Some(value.split("+").map(String::from).collect())
+                }
+                "reposts" => channel.reposts = parse_bool(key, value)?,
+                "greetings" => channel.greetings = parse_bool(key, value)?,
+                "greet_cooldown" => channel.greet_cooldown = value.parse()?,
+                _ => {
+                    return Err(Box::new(Error::new(&format!(
+                        "unknown channel option {} in {}",
+                        key, spec
+                    ))))
+                }
+            }
+        }
+        Ok(channel)
+    }
+
+    pub fn greet_cooldown(&self) -> Duration {
+        return Duration::seconds(self.greet_cooldown);
+    }
+
+    pub fn command_enabled(&self, command: &str) -> bool {
+        match &self.commands {
+            Some(commands) => commands.iter().any(|c| c == command),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bare_channel() {
+        let channel = ChannelConfig::parse("#rust").unwrap();
+        assert_eq!(channel.name, "#rust");
+        assert_eq!(channel.key, None);
+        assert_eq!(channel.commands, None);
+        assert!(channel.reposts);
+        assert!(channel.greetings);
+        assert_eq!(channel.greet_cooldown, 300);
+    }
+
+    #[test]
+    fn parses_options() {
+        let channel =
+            ChannelConfig::parse("#rust,key=s3cret,commands=ud+weather,reposts=off,greetings=no,greet_cooldown=60").unwrap();
+        assert_eq!(channel.key, Some(String::from("s3cret")));
+        assert_eq!(channel.commands, Some(vec![String::from("ud"), String::from("weather")]));
+        assert!(channel.command_enabled("ud"));
+        assert!(!channel.command_enabled("giphy"));
+        assert!(!channel.reposts);
+        assert!(!channel.greetings);
+        assert_eq!(channel.greet_cooldown(), Duration::seconds(60));
+    }
+
+    #[test]
+    fn keeps_equals_sign_in_key() {
+        let channel = ChannelConfig::parse("#rust,key=a=b").unwrap();
+        assert_eq!(channel.key, Some(String::from("a=b")));
+    }
+
+    #[test]
+    fn rejects_missing_name() {
+        assert!(ChannelConfig::parse("").is_err());
+        assert!(ChannelConfig::parse(",key=s3cret").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_option() {
+        let e = ChannelConfig::parse("#rust,colour=blue").unwrap_err();
+        assert_eq!(e.to_string(), "unknown channel option colour in #rust,colour=blue");
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let e = ChannelConfig::parse("#rust,reposts=maybe").unwrap_err();
+        assert_eq!(e.to_string(), "invalid value for reposts: maybe");
+        assert!(ChannelConfig::parse("#rust,greetings").is_err());
+        assert!(ChannelConfig::parse("#rust,greet_cooldown=soon").is_err());
+    }
+}
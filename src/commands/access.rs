@@ -0,0 +1,159 @@
+use crate::commands::{BotCommand, Handler, Level};
+use crate::permissions;
+use crate::{notice, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub static GRANT: BotCommand = BotCommand {
+    name: "grant",
+    aliases: &[],
+    usage: "<mask> <banned|user|trusted|admin|owner>",
+    help: "gives everyone matching a nick!user@host or $a:account mask a level",
+    level: Level::Admin,
+    handler: Handler::Inline(command_grant),
+    init: None,
+};
+
+pub static REVOKE: BotCommand = BotCommand {
+    name: "revoke",
+    aliases: &[],
+    usage: "<mask>",
+    help: "takes back whatever was granted to a mask",
+    level: Level::Admin,
+    handler: Handler::Inline(command_revoke),
+    init: None,
+};
+
+pub static ACCESS: BotCommand = BotCommand {
+    name: "access",
+    aliases: &["grants"],
+    usage: "",
+    help: "lists granted levels",
+    level: Level::Admin,
+    handler: Handler::Inline(command_access),
+    init: None,
+};
+
+// only owners can hand out or take back their own level
+fn can_manage(caller: Level, level: Level) -> bool {
+    return caller == Level::Owner || level < caller;
+}
+
+pub fn command_grant(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
+    let nick = &message.prefix.nick;
+    let parts: Vec<&str> = rest.split_whitespace().collect();
+    if parts.len() != 2 {
+        return notice(stream, nick, &format!("usage: {}", GRANT.usage()));
+    }
+
+    let (mask, level) = (parts[0], parts[1]);
+    if !permissions::is_full_mask(mask) {
+        return notice(stream, nick, &format!("{} isn't a nick!user@host or $a:account mask", mask));
+    }
+    let level = match Level::from_name(level) {
+        Some(level) => level,
+        None => return notice(stream, nick, &format!("unknown level {}", level)),
+    };
+
+    let caller = bot.level(message);
+    let existing = permissions::find(bot, mask);
+    if !can_manage(caller, level) || existing.map_or(false, |g| !can_manage(caller, g.level)) {
+        return notice(stream, nick, &String::from("you can only grant levels below your own"));
+    }
+    if level == Level::Banned {
+        if let Some(protected) = permissions::protected_by(bot, mask, caller) {
+            return notice(stream, nick, &format!("{} would also ban {}", mask, protected));
+        }
+        if let Some(other) = permissions::outranked_by(bot, mask, level) {
+            let msg = format!("{} would still be {} through {}, revoke that first", mask, other.level, other.mask);
+            return notice(stream, nick, &msg);
+        }
+    }
+
+    let granted_by = format!("{}!{}@{}", nick, message.prefix.realname, message.prefix.host);
+    permissions::grant(bot, mask, level, &granted_by)?;
+    log::info!("{} granted {} to {}", granted_by, level, mask);
+    notice(stream, nick, &format!("{} is now {}", mask, level))
+}
+
+pub fn command_revoke(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
+    let nick = &message.prefix.nick;
+    let mask = rest.trim();
+    if mask.len() == 0 {
+        return notice(stream, nick, &format!("usage: {}", REVOKE.usage()));
+    }
+
+    let existing = match permissions::find(bot, mask) {
+        Some(grant) => grant,
+        None => return notice(stream, nick, &format!("nothing is granted to {}", mask)),
+    };
+    if !can_manage(bot.level(message), existing.level) {
+        return notice(stream, nick, &String::from("you can only revoke levels below your own"));
+    }
+
+    permissions::revoke(bot, mask)?;
+    log::info!("{} revoked {} from {}", nick, existing.level, existing.mask);
+    notice(stream, nick, &format!("{} is no longer {}", existing.mask, existing.level))
+}
+
+pub fn command_access(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, _rest: &String) -> Result<()> {
+    let nick = &message.prefix.nick;
+    if bot.grants.len() == 0 {
+        return notice(stream, nick, &String::from("nothing is granted"));
+    }
+    let grants: Vec<String> = bot.grants.iter().map(|g| format!("{} ({})", g.mask, g.level)).collect();
+    notice(stream, nick, &format!("granted: {}", grants.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, connection, message, sent};
+
+    #[test]
+    fn owners_manage_every_level() {
+        for level in [Level::Banned, Level::User, Level::Trusted, Level::Admin, Level::Owner] {
+            assert!(can_manage(Level::Owner, level));
+        }
+    }
+
+    #[test]
+    fn admins_manage_levels_below_their_own() {
+        assert!(can_manage(Level::Admin, Level::Banned));
+        assert!(can_manage(Level::Admin, Level::User));
+        assert!(can_manage(Level::Admin, Level::Trusted));
+        assert!(!can_manage(Level::Admin, Level::Admin));
+        assert!(!can_manage(Level::Admin, Level::Owner));
+    }
+
+    #[test]
+    fn others_manage_only_lower_levels() {
+        assert!(can_manage(Level::Trusted, Level::User));
+        assert!(!can_manage(Level::User, Level::User));
+        assert!(!can_manage(Level::Banned, Level::Banned));
+    }
+
+    #[test]
+    fn refuses_bans_a_broader_grant_outranks() {
+        let mut bot = bot();
+        permissions::create_table(&bot.db).unwrap();
+        bot.owners.push(String::from("boss!b@example.org"));
+        let (mut stream, mut lines) = connection();
+        let boss = message(":boss!b@example.org PRIVMSG #rust :!grant");
+        let troll = message(":troll!t@corp.example PRIVMSG #rust :hi");
+
+        command_grant(&mut bot, &mut stream, &boss, &String::from("*!*@corp.example trusted")).unwrap();
+        command_grant(&mut bot, &mut stream, &boss, &String::from("troll!*@corp.example banned")).unwrap();
+        assert_eq!(
+            sent(&mut lines),
+            vec![
+                "NOTICE boss :*!*@corp.example is now trusted",
+                "NOTICE boss :troll!*@corp.example would still be trusted through *!*@corp.example, revoke that first",
+            ]
+        );
+        assert_eq!(bot.level(&troll), Level::Trusted);
+
+        // banning the broad mask itself replaces its grant
+        command_grant(&mut bot, &mut stream, &boss, &String::from("*!*@corp.example banned")).unwrap();
+        assert_eq!(sent(&mut lines), vec!["NOTICE boss :*!*@corp.example is now banned"]);
+        assert_eq!(bot.level(&troll), Level::Banned);
+    }
+}
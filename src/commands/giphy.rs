@@ -1,10 +1,13 @@
 extern crate serde;
 
-use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
+use crate::commands::{BotCommand, Handler, Level};
+use crate::workers::Job;
+use crate::{Error, Result};
 
-use std::env;
 use std::collections::HashMap;
+use std::time::Duration;
 
+use futures::future::BoxFuture;
 use rand::seq::IteratorRandom;
 
 use serde_json::Value;
@@ -19,27 +22,43 @@ struct GiphyResponse {
 }
 
 
-pub fn command(_bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
-    let parts: Vec<&str> = rest.split_whitespace().collect();
+pub static COMMAND: BotCommand = BotCommand {
+    name: "giphy",
+    aliases: &["gif"],
+    usage: "<search>",
+    help: "a random gif for the search",
+    level: Level::User,
+    handler: Handler::Worker(command, Duration::from_secs(10)),
+    init: None,
+};
+
+pub fn command(job: Job) -> BoxFuture<'static, Result<Vec<String>>> {
+    Box::pin(run(job))
+}
+
+async fn run(job: Job) -> Result<Vec<String>> {
+    let parts: Vec<&str> = job.rest.split_whitespace().collect();
     if parts.len() < 1 {
-        return Ok(());
+        return Ok(Vec::new());
     }
-    let key = env::var("GIPHY_API_KEY").unwrap();
+    let key = match &job.api_keys.giphy {
+        Some(key) => key,
+        None => return Err(Box::new(Error::new("no giphy api key configured"))),
+    };
     let url = format!("https://api.giphy.com/v1/gifs/search?api_key={}&q={}&rating=r", key, parts.join("+"));
-    let body = get_reqw_client()
+    let body = get_reqw_client(job.timeout)
         .get(&url)
-        .send()?
-        .json::<GiphyResponse>()?;
+        .send().await?
+        .json::<GiphyResponse>().await?;
 
+    let mut lines = Vec::new();
     let mut rng = rand::thread_rng();
     if body.data.len() > 0 {
         if let Some(item) = body.data.iter().choose(&mut rng) {
             if let Some(url) = item.get("url") {
-                let value = String::from(url.as_str().unwrap());
-                let target = &message.args[0];
-                say(stream, &target, &value)?;
+                lines.push(String::from(url.as_str().unwrap()));
             }
         }
     }
-    Ok(())
+    Ok(lines)
 }
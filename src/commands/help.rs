@@ -0,0 +1,43 @@
+use crate::commands::{find, BotCommand, Handler, Level, COMMANDS};
+use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
+
+pub static COMMAND: BotCommand = BotCommand {
+    name: "help",
+    aliases: &["commands"],
+    usage: "[command]",
+    help: "lists commands, or explains one",
+    level: Level::User,
+    handler: Handler::Inline(command),
+    init: None,
+};
+
+pub fn command(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
+    let target = &message.args[0];
+    let enabled = |name: &str| bot.command_enabled(target, name);
+
+    let name = rest.trim().trim_start_matches("!");
+    if name.len() == 0 {
+        let names: Vec<String> = COMMANDS
+            .iter()
+            .filter(|c| enabled(c.name))
+            .map(|c| format!("!{}", c.name))
+            .collect();
+        let msg = format!("commands: {} (!help <command> for more)", names.join(" "));
+        return say(stream, target, &msg);
+    }
+
+    let msg = match find(name) {
+        Some(command) if enabled(command.name) => {
+            let mut msg = format!("{} - {}", command.usage(), command.help);
+            if command.aliases.len() > 0 {
+                msg.push_str(&format!(" (also {})", command.aliases.join(", ")));
+            }
+            if command.level > Level::User {
+                msg.push_str(&format!(" [{} only]", command.level));
+            }
+            msg
+        }
+        _ => format!("no such command: {}", name),
+    };
+    say(stream, target, &msg)
+}
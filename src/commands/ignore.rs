@@ -0,0 +1,102 @@
+use chrono::Utc;
+
+use crate::commands::{BotCommand, Handler, Level};
+use crate::ignore::{self, IgnoreRule, Scope};
+use crate::permissions;
+use crate::{notice, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub static COMMAND: BotCommand = BotCommand {
+    name: "ignore",
+    aliases: &[],
+    usage: "add [all|commands|urls|greetings] <mask|/regex/> [30m|2h|1d] | del <mask|/regex/> | list",
+    help: "manages who the bot doesn't listen to, for everything or just some of it",
+    level: Level::Admin,
+    handler: Handler::Inline(command),
+    init: None,
+};
+
+pub fn command(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
+    let nick = &message.prefix.nick;
+    let parts: Vec<&str> = rest.split_whitespace().collect();
+    match parts.first() {
+        Some(&"add") => add(bot, stream, message, &parts[1..]),
+        Some(&"del") if parts.len() == 2 => {
+            let reply = match ignore::remove(bot, parts[1])? {
+                0 if bot.ignores.iter().any(|r| r.pattern == parts[1]) => {
+                    format!("{} comes from the config file", parts[1])
+                }
+                0 => format!("{} isn't ignored", parts[1]),
+                _ => format!("no longer ignoring {}", parts[1]),
+            };
+            notice(stream, nick, &reply)
+        }
+        Some(&"list") => list(bot, stream, message),
+        _ => notice(stream, nick, &format!("usage: {}", COMMAND.usage())),
+    }
+}
+
+fn add(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, args: &[&str]) -> Result<()> {
+    let nick = &message.prefix.nick;
+    let mut args = args.to_vec();
+
+    let scope = match args.first().and_then(|a| Scope::from_name(a)) {
+        Some(scope) => {
+            args.remove(0);
+            scope
+        }
+        None => Scope::All,
+    };
+
+    let expires = match args.len() {
+        1 => None,
+        2 => match ignore::parse_duration(args[1]) {
+            Some(duration) => Some(Utc::now() + duration),
+            None => return notice(stream, nick, &format!("can't make sense of {}, try 30m, 2h or 1d", args[1])),
+        },
+        _ => return notice(stream, nick, &format!("usage: {}", COMMAND.usage())),
+    };
+
+    let rule = match IgnoreRule::parse(args[0], scope, expires) {
+        Ok(rule) => rule,
+        Err(e) => return notice(stream, nick, &e.to_string()),
+    };
+    let caller = bot.level(message);
+    let protected = permissions::protected_masks(bot, caller);
+    if let Some(mask) = protected.iter().find(|mask| rule.overlaps(bot, mask)) {
+        return notice(stream, nick, &format!("{} would also ignore {}", rule.pattern, mask));
+    }
+
+    let added_by = format!("{}!{}@{}", nick, message.prefix.realname, message.prefix.host);
+    log::info!("{} ignored {} ({})", added_by, rule.pattern, rule.scope);
+    let reply = match expires {
+        Some(_) => format!("ignoring {} for {} ({})", rule.pattern, args[1], rule.scope),
+        None => format!("ignoring {} ({})", rule.pattern, rule.scope),
+    };
+    ignore::add(bot, rule, &added_by)?;
+    notice(stream, nick, &reply)
+}
+
+fn list(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage) -> Result<()> {
+    ignore::prune(bot)?;
+    if bot.ignores.len() == 0 {
+        return notice(stream, &message.prefix.nick, &String::from("nobody is ignored"));
+    }
+
+    let now = Utc::now();
+    let rules: Vec<String> = bot
+        .ignores
+        .iter()
+        .map(|rule| {
+            let mut line = format!("{} ({}", rule.pattern, rule.scope);
+            if rule.id.is_none() {
+                line.push_str(", config");
+            }
+            if let Some(expires) = rule.expires {
+                line.push_str(&format!(", {}m left", (expires - now).num_minutes() + 1));
+            }
+            line.push_str(")");
+            line
+        })
+        .collect();
+    notice(stream, &message.prefix.nick, &format!("ignoring: {}", rules.join(", ")))
+}
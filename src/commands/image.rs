@@ -1,8 +1,13 @@
 extern crate image;
 extern crate color_space;
 
-use crate::{IrcMessage, IrcConnection, IrcBot, Result, Error, say};
+use std::time::Duration;
 
+use crate::commands::{BotCommand, Handler, Level};
+use crate::workers::Job;
+use crate::{Result, Error};
+
+use futures::future::BoxFuture;
 use reqwest::StatusCode;
 use color_space::{Rgb, CompareCie2000};
 use image::imageops;
@@ -145,26 +150,39 @@ fn nearest_color(target: &Rgb) -> &Color {
     return &COLORS[0];
 }
 
-pub fn command(_bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
-    if rest.len() < 1 {
-        return Ok(());
+pub static COMMAND: BotCommand = BotCommand {
+    name: "image",
+    aliases: &["img"],
+    usage: "<url>",
+    help: "draws an image in irc colors",
+    level: Level::User,
+    handler: Handler::Worker(command, Duration::from_secs(20)),
+    init: None,
+};
+
+pub fn command(job: Job) -> BoxFuture<'static, Result<Vec<String>>> {
+    Box::pin(run(job))
+}
+
+async fn run(job: Job) -> Result<Vec<String>> {
+    if job.rest.len() < 1 {
+        return Ok(Vec::new());
     }
 
-    let url = rest;
-    let result = get_reqw_client().get(url).send()?;
+    let url = &job.rest;
+    let result = get_reqw_client(job.timeout).get(url).send().await?;
     if result.status() != StatusCode::OK {
         let msg = format!("could not load {}: {:?}", url, result);
         return Err(Box::new(Error::new(&msg)))
     }
-    let body = result.bytes()?;
+    let body = result.bytes().await?;
     let img = image::load_from_memory(&body)?;
     let (max_width, max_height) = (20, 20);
 
     let thumb = imageops::resize(&img, max_width, max_height, imageops::FilterType::Lanczos3);
     let (thumb_width, thumb_height) = thumb.dimensions();
 
-    let target = &message.args[0];
-
+    let mut lines = Vec::new();
     for y in 0..thumb_height {
         let mut row = String::new();
 
@@ -177,8 +195,8 @@ pub fn command(_bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessa
             let cell = format!("\x0301,{:02} ", nearest.code);
             row.push_str(&cell);
         }
-        say(stream, target, &row)?;
+        lines.push(row);
     }
 
-    Ok(())
+    Ok(lines)
 }
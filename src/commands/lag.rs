@@ -0,0 +1,23 @@
+use crate::commands::{BotCommand, Handler, Level};
+use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
+
+pub static COMMAND: BotCommand = BotCommand {
+    name: "lag",
+    aliases: &["ping"],
+    usage: "",
+    help: "round trip time to the server",
+    level: Level::User,
+    handler: Handler::Inline(command),
+    init: None,
+};
+
+
+pub fn command(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, _rest: &String) -> Result<()> {
+    let target = &message.args[0];
+    let msg = match bot.lag.lag() {
+        Some(lag) => format!("lag to {} is {}ms", bot.host, lag.as_millis()),
+        None => format!("lag to {} hasn't been measured yet", bot.host),
+    };
+    say(stream, target, &msg)?;
+    Ok(())
+}
@@ -1,6 +1,272 @@
+pub mod access;
 // pub mod giphy;
+pub mod help;
+pub mod ignore;
 // pub mod image;
+pub mod lag;
 pub mod nega;
+pub mod reload;
+pub mod seen;
 pub mod strain;
 pub mod ud;
 pub mod weather;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::workers::{Job, WorkerCommand};
+use crate::{notice, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub type Command = fn(
+    bot: &mut IrcBot,
+    stream: &mut IrcConnection,
+    message: &IrcMessage,
+    rest: &String,
+) -> Result<()>;
+
+pub enum Handler {
+    // runs on the read loop, for quick commands that need the bot itself
+    Inline(Command),
+    // runs as its own task, for anything that waits on the network
+    Worker(WorkerCommand, Duration),
+}
+
+// runs once against the database before connecting
+pub type Init = fn(db: &Connection) -> Result<()>;
+
+// who may run a command, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    // can't run anything
+    Banned,
+    User,
+    // not held to cooldowns
+    Trusted,
+    // channel operators (@ or above) in the channel the command was sent to
+    Op,
+    Admin,
+    // matches one of the network's owner masks, or was granted owner
+    Owner,
+}
+
+impl Level {
+    // levels that can be granted, op comes from the channel instead
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "banned" => Some(Level::Banned),
+            "user" | "normal" => Some(Level::User),
+            "trusted" => Some(Level::Trusted),
+            "admin" => Some(Level::Admin),
+            "owner" => Some(Level::Owner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Banned => write!(f, "banned"),
+            Level::User => write!(f, "user"),
+            Level::Trusted => write!(f, "trusted"),
+            Level::Op => write!(f, "op"),
+            Level::Admin => write!(f, "admin"),
+            Level::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+pub struct BotCommand {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    // arguments after the command name, "" if it takes none
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub level: Level,
+    pub handler: Handler,
+    pub init: Option<Init>,
+}
+
+impl BotCommand {
+    pub fn matches(&self, name: &str) -> bool {
+        return self.name == name || self.aliases.contains(&name);
+    }
+
+    pub fn usage(&self) -> String {
+        if self.usage.len() == 0 {
+            return format!("!{}", self.name);
+        }
+        format!("!{} {}", self.name, self.usage)
+    }
+}
+
+pub static COMMANDS: &[&BotCommand] = &[
+    &access::GRANT,
+    &access::REVOKE,
+    &access::ACCESS,
+    &help::COMMAND,
+    &ignore::COMMAND,
+    &lag::COMMAND,
+    &nega::NEGA,
+    &nega::KUDOS,
+    &reload::COMMAND,
+    &seen::COMMAND,
+    &strain::COMMAND,
+    &ud::COMMAND,
+    &weather::COMMAND,
+    // &giphy::COMMAND,
+    // &image::COMMAND,
+];
+
+pub fn find(name: &str) -> Option<&'static BotCommand> {
+    return COMMANDS.iter().find(|c| c.matches(name)).copied();
+}
+
+pub fn init(db: &Connection) -> Result<()> {
+    for command in COMMANDS {
+        if let Some(init_fn) = command.init {
+            init_fn(db)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn dispatch(
+    bot: &mut IrcBot,
+    stream: &mut IrcConnection,
+    msg: &IrcMessage,
+    name: &String,
+    rest: &String,
+) -> Result<()> {
+    let command = match find(name) {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+
+    if !bot.command_enabled(&msg.args[0], command.name) {
+        log::debug!("{} is disabled in {}", command.name, msg.args[0]);
+        return Ok(());
+    }
+
+    let level = bot.level(msg);
+    if level == Level::Banned {
+        log::debug!("{} is banned, ignoring !{}", msg.prefix.nick, command.name);
+        return Ok(());
+    }
+    if level < command.level {
+        let reply = format!("!{} needs {} access", command.name, command.level);
+        return notice(stream, &msg.prefix.nick, &reply);
+    }
+
+    let casemapping = bot.isupport.casemapping;
+    let user = casemapping.fold(&format!("{}@{}", msg.prefix.realname, msg.prefix.host));
+    let channel = casemapping.fold(&msg.args[0]);
+    let now = Instant::now();
+
+    // trusted users aren't held to cooldowns, everyone else hears about them once
+    let mut reply = None;
+    if level < Level::Trusted {
+        if let Some(wait) = bot.limiter.wait(&user, &channel, command.name, now) {
+            reply = Some((wait, format!("slow down, try !{} again in {}s", command.name, wait.as_secs() + 1)));
+        }
+    }
+    // but the http budget is shared by every network, so it holds for everyone
+    if let (None, Handler::Worker(..)) = (&reply, &command.handler) {
+        let mut budget = bot.http_budget();
+        if !budget.try_take(1.0) {
+            let wait = budget.time_until(1.0);
+            reply = Some((wait, format!("too many lookups, try again in {}s", wait.as_secs() + 1)));
+        }
+    }
+
+    if let Some((wait, reply)) = reply {
+        log::debug!("rate limited !{} from {}", command.name, msg.prefix.nick);
+        if bot.limiter.warn(&user, wait, now) {
+            notice(stream, &msg.prefix.nick, &reply)?;
+        }
+        return Ok(());
+    }
+    if level < Level::Trusted {
+        bot.limiter.record(&user, &channel, command.name, now);
+    }
+
+    match command.handler {
+        Handler::Inline(handler_fn) => {
+            if let Err(e) = handler_fn(bot, stream, msg, rest) {
+                log::error!("command {} errored: {}", command.name, e);
+            }
+        }
+        Handler::Worker(handler_fn, timeout) => {
+            let job = Job {
+                target: msg.args[0].clone(),
+                rest: rest.clone(),
+                timeout: timeout,
+                api_keys: bot.api_keys.clone(),
+            };
+            if !bot.workers.submit(command.name, handler_fn, stream, job) {
+                log::warn!("too many commands running, dropping !{}", command.name);
+                bot.http_budget().refund(1.0);
+                notice(stream, &msg.prefix.nick, &String::from("busy, try again in a bit"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permissions::Grant;
+    use crate::ratelimit::http_budget;
+    use crate::testutil::{bot, connection, message, sent};
+
+    #[test]
+    fn parses_level_names() {
+        assert_eq!(Level::from_name("banned"), Some(Level::Banned));
+        assert_eq!(Level::from_name("user"), Some(Level::User));
+        assert_eq!(Level::from_name("normal"), Some(Level::User));
+        assert_eq!(Level::from_name("trusted"), Some(Level::Trusted));
+        assert_eq!(Level::from_name("admin"), Some(Level::Admin));
+        assert_eq!(Level::from_name("owner"), Some(Level::Owner));
+        // ops come from channel modes, they can't be granted
+        assert_eq!(Level::from_name("op"), None);
+        assert_eq!(Level::from_name("Owner"), None);
+        assert_eq!(Level::from_name(""), None);
+    }
+
+    #[test]
+    fn level_names_round_trip() {
+        for level in [Level::Banned, Level::User, Level::Trusted, Level::Admin, Level::Owner] {
+            assert_eq!(Level::from_name(&level.to_string()), Some(level));
+        }
+    }
+
+    #[test]
+    fn levels_are_ordered() {
+        assert!(Level::Banned < Level::User);
+        assert!(Level::User < Level::Trusted);
+        assert!(Level::Trusted < Level::Op);
+        assert!(Level::Op < Level::Admin);
+        assert!(Level::Admin < Level::Owner);
+    }
+
+    #[test]
+    fn trusted_users_share_the_http_budget() {
+        let mut bot = bot();
+        bot.http_budget = http_budget(1);
+        bot.http_budget().try_take(1.0);
+        bot.grants.push(Grant {
+            mask: String::from("$a:helper"),
+            level: Level::Trusted,
+        });
+        let (mut stream, mut lines) = connection();
+        let msg = message("@account=helper :helper!h@example.org PRIVMSG #rust :!weather oslo");
+
+        dispatch(&mut bot, &mut stream, &msg, &String::from("weather"), &String::from("oslo")).unwrap();
+        let sent = sent(&mut lines);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with("NOTICE helper :too many lookups"));
+    }
+}
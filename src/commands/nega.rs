@@ -1,6 +1,7 @@
 use chrono::Utc;
-use rusqlite::params;
+use rusqlite::{params, Connection};
 
+use crate::commands::{BotCommand, Handler, Level};
 use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
 
 static CREATE_TABLE_NEGA_VOTES: &str = "
@@ -14,9 +15,29 @@ CREATE TABLE IF NOT EXISTS nega_votes (
 );
 ";
 
-
-pub fn init(bot: &mut IrcBot) -> Result<()> {
-    bot.db.execute(CREATE_TABLE_NEGA_VOTES, [])?;
+pub static NEGA: BotCommand = BotCommand {
+    name: "nega",
+    aliases: &[],
+    usage: "<nick> <reason>",
+    help: "records a vote against someone in the channel",
+    level: Level::User,
+    handler: Handler::Inline(command_nega),
+    init: Some(init),
+};
+
+pub static KUDOS: BotCommand = BotCommand {
+    name: "kudos",
+    aliases: &[],
+    usage: "<nick> <reason>",
+    help: "records a vote for someone in the channel",
+    level: Level::User,
+    handler: Handler::Inline(command_kudos),
+    init: None,
+};
+
+
+pub fn init(db: &Connection) -> Result<()> {
+    db.execute(CREATE_TABLE_NEGA_VOTES, [])?;
     Ok(())
 }
 
@@ -36,6 +57,11 @@ pub fn record_vote(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMe
     }
 
     let target_nick = target_nick_opt.unwrap();
+    let channel = &message.args[0];
+    if !bot.members.is_present(channel, target_nick) {
+        say(stream, channel, &format!("{} isn't here", target_nick))?;
+        return Ok(());
+    }
 
     let target_opt = bot.find_ident_by_nick(&target_nick.to_string());
     if target_opt.is_none() {
@@ -0,0 +1,45 @@
+use tokio::sync::oneshot;
+
+use crate::commands::{BotCommand, Handler, Level};
+use crate::reload::ReloadRequest;
+use crate::{notice, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub static COMMAND: BotCommand = BotCommand {
+    name: "reload",
+    aliases: &["rehash"],
+    usage: "",
+    help: "re-reads the config file, applying what it can without reconnecting",
+    level: Level::Owner,
+    handler: Handler::Inline(command),
+    init: None,
+};
+
+pub fn command(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, _rest: &String) -> Result<()> {
+    let nick = message.prefix.nick.clone();
+    let (done, result) = oneshot::channel();
+    let request = ReloadRequest {
+        network: bot.network.clone(),
+        nick: nick.clone(),
+        done: done,
+    };
+
+    let sent = match &bot.reload {
+        Some(reload) => reload.send(request).is_ok(),
+        None => false,
+    };
+    if !sent {
+        return notice(stream, &nick, &String::from("reloading isn't available"));
+    }
+
+    // what changed is reported by the connection once it applies the config,
+    // only failures come back here
+    let mut stream = stream.clone();
+    tokio::spawn(async move {
+        if let Ok(Err(e)) = result.await {
+            for line in e.to_string().lines() {
+                let _ = notice(&mut stream, &nick, &format!("reload failed: {}", line.trim()));
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension};
+
+use crate::commands::{BotCommand, Handler, Level};
+use crate::utils::time_ago;
+use crate::{say, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub static COMMAND: BotCommand = BotCommand {
+    name: "seen",
+    aliases: &[],
+    usage: "<nick>",
+    help: "when someone was last around, and what they said",
+    level: Level::User,
+    handler: Handler::Inline(command),
+    init: None,
+};
+
+struct Seen {
+    nick: String,
+    last_seen: DateTime<Utc>,
+    last_event: Option<String>,
+    last_event_channel: Option<String>,
+    last_channel: Option<String>,
+    last_message: Option<String>,
+    last_spoke: Option<DateTime<Utc>>,
+}
+
+fn find(bot: &mut IrcBot, nick: &str) -> Result<Option<Seen>> {
+    let seen = bot.db.query_row(
+        "SELECT nick, last_seen, last_event, last_event_channel, last_channel, last_message, last_spoke
+         FROM seen_idents WHERE network=?1 AND nick_key=?2 ORDER BY last_seen DESC LIMIT 1",
+        params![bot.network, bot.nick_key(nick)],
+        |row| {
+            Ok(Seen {
+                nick: row.get(0)?,
+                last_seen: row.get(1)?,
+                last_event: row.get(2)?,
+                last_event_channel: row.get(3)?,
+                last_channel: row.get(4)?,
+                last_message: row.get(5)?,
+                last_spoke: row.get(6)?,
+            })
+        },
+    ).optional()?;
+    Ok(seen)
+}
+
+pub fn command(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
+    let target = &message.args[0];
+    let nick = match rest.split_whitespace().next() {
+        Some(nick) => nick,
+        None => return Ok(()),
+    };
+
+    if bot.is_me(nick) {
+        return say(stream, target, &String::from("I'm right here"));
+    }
+    if bot.isupport.casemapping.equals(nick, &message.prefix.nick) {
+        return say(stream, target, &String::from("that's you"));
+    }
+
+    let here = bot.members.is_present(target, nick);
+    let seen = match find(bot, nick)? {
+        Some(seen) => seen,
+        None if here => return say(stream, target, &format!("{} is here, but hasn't said anything", nick)),
+        None => return say(stream, target, &format!("I haven't seen {}", nick)),
+    };
+
+    // what happened or was said elsewhere, maybe somewhere secret, stays there
+    let casemapping = bot.isupport.casemapping;
+    let event = match (&seen.last_event, &seen.last_event_channel) {
+        (Some(event), Some(channel)) if casemapping.equals(channel, target) => Some(event),
+        (Some(event), None) => Some(event),
+        _ => None,
+    };
+    let mut reply = match (here, event) {
+        (true, _) => format!("{} is here", seen.nick),
+        (false, Some(event)) => format!("{} was last seen {} {}", seen.nick, time_ago(seen.last_seen), event),
+        (false, None) => format!("{} was last seen {}", seen.nick, time_ago(seen.last_seen)),
+    };
+    if let (Some(spoke), Some(channel), Some(text)) = (seen.last_spoke, &seen.last_channel, &seen.last_message) {
+        if casemapping.equals(channel, target) {
+            reply.push_str(&format!(", last said {}: {}", time_ago(spoke), text));
+        }
+    }
+    say(stream, target, &reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, connection, message, sent};
+    use crate::CREATE_TABLE_SEEN_IDENTS;
+
+    #[test]
+    fn keeps_events_in_their_channel() {
+        let mut bot = bot();
+        bot.db.execute_batch(CREATE_TABLE_SEEN_IDENTS).unwrap();
+        let (mut stream, mut lines) = connection();
+        let asker = message(":bob!b@example.org PRIVMSG #rust :!seen alice");
+
+        bot.record_event(&message(":alice!al@example.org PART #secret"), "leaving #secret", Some("#secret")).unwrap();
+        command(&mut bot, &mut stream, &asker, &String::from("alice")).unwrap();
+        let reply = sent(&mut lines).join("\n");
+        assert!(reply.starts_with("PRIVMSG #rust :alice was last seen"));
+        assert!(!reply.contains("secret"));
+
+        let here = message(":bob!b@example.org PRIVMSG #Secret :!seen alice");
+        command(&mut bot, &mut stream, &here, &String::from("alice")).unwrap();
+        assert!(sent(&mut lines)[0].ends_with("leaving #secret"));
+
+        bot.record_event(&message(":alice!al@example.org QUIT :bye"), "quitting (bye)", None).unwrap();
+        command(&mut bot, &mut stream, &asker, &String::from("alice")).unwrap();
+        assert!(sent(&mut lines)[0].ends_with("quitting (bye)"));
+    }
+}
@@ -3,7 +3,13 @@ extern crate soup;
 use soup::prelude::*;
 use soup::Soup;
 
-use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use crate::commands::{BotCommand, Handler, Level};
+use crate::workers::Job;
+use crate::Result;
 
 use crate::utils::get_reqw_client;
 
@@ -26,20 +32,34 @@ fn parse_strain(html: &String) -> Option<String> {
     return None;
 }
 
-pub fn command(_bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
-    let parts: Vec<&str> = rest.split_whitespace().collect();
+pub static COMMAND: BotCommand = BotCommand {
+    name: "strain",
+    aliases: &[],
+    usage: "<name>",
+    help: "leafly's description of a strain",
+    level: Level::User,
+    handler: Handler::Worker(command, Duration::from_secs(15)),
+    init: None,
+};
+
+pub fn command(job: Job) -> BoxFuture<'static, Result<Vec<String>>> {
+    Box::pin(run(job))
+}
+
+async fn run(job: Job) -> Result<Vec<String>> {
+    let parts: Vec<&str> = job.rest.split_whitespace().collect();
     if parts.len() < 1 {
-        return Ok(());
+        return Ok(Vec::new());
     }
     let name = parts.join("-");
     let url = format!("https://www.leafly.com/strains/{}", name);
-    let body = get_reqw_client()
+    let body = get_reqw_client(job.timeout)
         .get(&url)
-        .send()?
-        .text()?;
+        .send().await?
+        .text().await?;
+    let mut lines = Vec::new();
     if let Some(result) = parse_strain(&body) {
-        let target = &message.args[0];
-        say(stream, target, &result)?;
+        lines.push(result);
     }
-    Ok(())
+    Ok(lines)
 }
@@ -1,9 +1,13 @@
 extern crate serde;
 
-use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
+use crate::commands::{BotCommand, Handler, Level};
+use crate::workers::Job;
+use crate::Result;
 
 use std::collections::HashMap;
+use std::time::Duration;
 
+use futures::future::BoxFuture;
 use serde_json::Value;
 use serde::Deserialize;
 
@@ -15,20 +19,33 @@ struct UdResponse {
     list: Vec<HashMap<String, Value>>,
 }
 
-pub fn command(_bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
-    let parts: Vec<&str> = rest.split_whitespace().collect();
+pub static COMMAND: BotCommand = BotCommand {
+    name: "ud",
+    aliases: &["urban"],
+    usage: "<term>",
+    help: "top urban dictionary definition",
+    level: Level::User,
+    handler: Handler::Worker(command, Duration::from_secs(10)),
+    init: None,
+};
+
+pub fn command(job: Job) -> BoxFuture<'static, Result<Vec<String>>> {
+    Box::pin(run(job))
+}
+
+async fn run(job: Job) -> Result<Vec<String>> {
+    let parts: Vec<&str> = job.rest.split_whitespace().collect();
     if parts.len() < 1 {
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     let url = format!("https://api.urbandictionary.com/v0/define?term={}", parts.join("+"));
-    let resp = get_reqw_client().get(&url).send()?.json::<UdResponse>()?;
+    let resp = get_reqw_client(job.timeout).get(&url).send().await?.json::<UdResponse>().await?;
+    let mut lines = Vec::new();
     if resp.list.len() > 0 {
         if let Some(def) = resp.list[0].get("definition") {
-            let target = &message.args[0];
-            let value = String::from(def.as_str().unwrap());
-            say(stream, &target, &value)?;
+            lines.push(String::from(def.as_str().unwrap()));
         }
     }
-    Ok(())
+    Ok(lines)
 }
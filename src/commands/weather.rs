@@ -1,10 +1,14 @@
 extern crate serde;
 
-use crate::{IrcMessage, IrcConnection, IrcBot, Result, say};
+use crate::commands::{BotCommand, Handler, Level};
+use crate::workers::Job;
+use crate::{Error, Result};
 
-use std::env;
+use std::time::Duration;
 use std::collections::HashMap;
 
+use futures::future::BoxFuture;
+
 use crate::utils::get_reqw_client;
 
 use serde::Deserialize;
@@ -20,24 +24,40 @@ fn k2f(k: f32) -> f32 {
 }
 
 
-pub fn command(_bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage, rest: &String) -> Result<()> {
-    let parts: Vec<&str> = rest.split_whitespace().collect();
+pub static COMMAND: BotCommand = BotCommand {
+    name: "weather",
+    aliases: &["w"],
+    usage: "<city>",
+    help: "current temperature for a US city",
+    level: Level::User,
+    handler: Handler::Worker(command, Duration::from_secs(10)),
+    init: None,
+};
+
+pub fn command(job: Job) -> BoxFuture<'static, Result<Vec<String>>> {
+    Box::pin(run(job))
+}
+
+async fn run(job: Job) -> Result<Vec<String>> {
+    let parts: Vec<&str> = job.rest.split_whitespace().collect();
     if parts.len() < 1 {
-        return Ok(());
+        return Ok(Vec::new());
     }
 
-    let key = env::var("OPENWEATHER_API_KEY")?;
+    let key = match &job.api_keys.openweather {
+        Some(key) => key,
+        None => return Err(Box::new(Error::new("no openweather api key configured"))),
+    };
     let url = format!("https://api.openweathermap.org/data/2.5/weather?q={},us&APPID={}", parts[0], key);
     log::debug!("GET {}", url);
-    let result = get_reqw_client()
+    let result = get_reqw_client(job.timeout)
         .get(&url)
-        .send()?;
-    let body = result.json::<WeatherResponse>()?;
+        .send().await?;
+    let body = result.json::<WeatherResponse>().await?;
+    let mut lines = Vec::new();
     if let Some(temp) = body.main.get("temp") {
         let feels_like = k2f(*temp);
-        let msg = format!("the actual temp in {} is {:.0}f", body.name, feels_like);
-        let target = &message.args[0];
-        say(stream, &target, &msg)?;
+        lines.push(format!("the actual temp in {} is {:.0}f", body.name, feels_like));
     }
-    Ok(())
+    Ok(lines)
 }
@@ -0,0 +1,290 @@
+extern crate toml;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::channels::ChannelConfig;
+use crate::commands;
+use crate::ignore::{IgnoreRule, Scope};
+use crate::permissions;
+use crate::ratelimit::Cooldowns;
+use crate::sasl::{self, Mechanism};
+use crate::tls::{self, TlsOptions};
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // shared by every network, otherwise each gets {nick}-at-{host}.db
+    pub database: Option<String>,
+    #[serde(default)]
+    pub api_keys: ApiKeys,
+    // calls to web APIs allowed per minute, across every network
+    pub http_per_minute: Option<u32>,
+    pub networks: Vec<NetworkConfig>,
+}
+
+// Keys for the web APIs commands use, falling back to the environment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiKeys {
+    pub openweather: Option<String>,
+    pub giphy: Option<String>,
+}
+
+impl ApiKeys {
+    pub fn with_env(mut self) -> ApiKeys {
+        if self.openweather.is_none() {
+            self.openweather = env::var("OPENWEATHER_API_KEY").ok();
+        }
+        if self.giphy.is_none() {
+            self.giphy = env::var("GIPHY_API_KEY").ok();
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub name: Option<String>,
+    pub host: String,
+    // fallbacks tried in turn after host when connections fail
+    #[serde(default)]
+    pub servers: Vec<String>,
+    pub nick: String,
+    // tried in order when nick is taken
+    #[serde(default)]
+    pub alt_nicks: Vec<String>,
+    // defaults to the nick
+    pub realname: Option<String>,
+    pub channels: Vec<ChannelConfig>,
+    // commands available on this network, None enables every command
+    pub commands: Option<Vec<String>>,
+    // what the bot answers greetings with, and which words count as one
+    pub greetings: Option<Vec<String>>,
+    // nicks, nick!user@host or $a:account masks where * and ? match anything,
+    // or /regex/ against nick!user@host. more can be added with !ignore
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    // nick!user@host or $a:account masks that may run owner commands like
+    // !reload, * and ? match anything
+    #[serde(default)]
+    pub owners: Vec<String>,
+    #[serde(default)]
+    pub cooldowns: Cooldowns,
+    pub caps: Option<Vec<String>>,
+    pub sasl: Option<String>,
+    pub sasl_credentials: Option<String>,
+    #[serde(default)]
+    pub no_tls: bool,
+    // lines a single reply may take before it is truncated
+    pub max_lines: Option<usize>,
+    // seconds between our own PINGs, and of silence before reconnecting
+    pub ping_interval: Option<u64>,
+    pub ping_timeout: Option<u64>,
+    #[serde(default)]
+    pub tls: TlsOptions,
+}
+
+impl NetworkConfig {
+    pub fn new(host: &str, nick: &str) -> NetworkConfig {
+        return NetworkConfig {
+            name: None,
+            host: String::from(host),
+            servers: Vec::new(),
+            nick: String::from(nick),
+            alt_nicks: Vec::new(),
+            realname: None,
+            channels: Vec::new(),
+            commands: None,
+            greetings: None,
+            ignore: Vec::new(),
+            owners: Vec::new(),
+            cooldowns: Cooldowns::default(),
+            caps: None,
+            sasl: None,
+            sasl_credentials: None,
+            no_tls: false,
+            max_lines: None,
+            ping_interval: None,
+            ping_timeout: None,
+            tls: TlsOptions::default(),
+        };
+    }
+
+    pub fn name(&self) -> String {
+        return self.name.clone().unwrap_or(self.host.clone());
+    }
+
+    pub fn realname(&self) -> String {
+        return self.realname.clone().unwrap_or(self.nick.clone());
+    }
+
+    pub fn db_path(&self) -> String {
+        return format!("./{nick}-at-{host}.db", host = self.host, nick = self.nick);
+    }
+
+    pub fn sasl_mechanism(&self) -> Result<Option<Mechanism>> {
+        match self.sasl.as_deref() {
+            Some("plain") => {
+                let mechanism = match &self.sasl_credentials {
+                    Some(path) => sasl::plain_from_file(path)?,
+                    None => sasl::plain_from_env()?,
+                };
+                Ok(Some(mechanism))
+            }
+            Some("external") => {
+                if self.tls.client_cert.is_none() {
+                    return Err(Box::new(Error::new("sasl external requires a client certificate")));
+                }
+                Ok(Some(Mechanism::External))
+            }
+            Some(other) => Err(Box::new(Error::new(&format!("unknown sasl mechanism: {}", other)))),
+            None => Ok(None),
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        for server in std::iter::once(&self.host).chain(self.servers.iter()) {
+            match server.rsplit_once(":") {
+                Some((host, port)) if host.len() > 0 && port.parse::<u16>().is_ok() => {}
+                _ => errors.push(format!("server {} should be host:port", server)),
+            }
+        }
+
+        for nick in std::iter::once(&self.nick).chain(self.alt_nicks.iter()) {
+            let first = nick.chars().next().unwrap_or('0');
+            if first.is_ascii_digit() || first == '-' || nick.contains(|c: char| " ,*?!@#:".contains(c)) {
+                errors.push(format!("invalid nick: {:?}", nick));
+            }
+        }
+
+        if self.channels.len() < 1 {
+            errors.push(String::from("no channels"));
+        }
+        let mut seen = HashSet::new();
+        for channel in &self.channels {
+            if !channel.name.starts_with(|c: char| "#&+!".contains(c)) || channel.name.contains(" ") {
+                errors.push(format!("invalid channel name: {:?}", channel.name));
+            }
+            if !seen.insert(channel.name.to_lowercase()) {
+                errors.push(format!("{} is listed twice", channel.name));
+            }
+            for command in channel.commands.iter().flatten() {
+                if let Some(error) = command_error(command) {
+                    errors.push(format!("{}: {}", channel.name, error));
+                }
+            }
+            if channel.greet_cooldown < 0 {
+                errors.push(format!("{}: greet_cooldown can't be negative", channel.name));
+            }
+        }
+
+        for command in self.commands.iter().flatten() {
+            if let Some(error) = command_error(command) {
+                errors.push(error);
+            }
+        }
+
+        for command in self.cooldowns.commands.keys() {
+            if let Some(error) = command_error(command) {
+                errors.push(format!("cooldowns: {}", error));
+            }
+        }
+
+        for pattern in &self.ignore {
+            if let Err(e) = IgnoreRule::parse(pattern, Scope::All, None) {
+                errors.push(format!("ignore {:?}: {}", pattern, e));
+            }
+        }
+
+        for mask in &self.owners {
+            if !permissions::is_full_mask(mask) {
+                errors.push(format!("owners {:?}: use nick!user@host or $a:account", mask));
+            }
+        }
+
+        if let Some(greetings) = &self.greetings {
+            if greetings.iter().all(|g| g.trim().len() == 0) {
+                errors.push(String::from("greetings can't be empty"));
+            }
+        }
+
+        if self.max_lines == Some(0) {
+            errors.push(String::from("max_lines must be at least 1"));
+        }
+        if let (Some(interval), Some(timeout)) = (self.ping_interval, self.ping_timeout) {
+            if timeout <= interval {
+                errors.push(String::from("ping_timeout must be longer than ping_interval"));
+            }
+        }
+
+        if let Err(e) = self.sasl_mechanism() {
+            errors.push(e.to_string());
+        }
+        if self.tls.client_cert.is_some() != self.tls.client_key.is_some() {
+            errors.push(String::from("tls client_cert and client_key go together"));
+        }
+        if !self.no_tls {
+            if let Err(e) = tls::client_config(&self.tls) {
+                errors.push(format!("tls: {}", e));
+            }
+        }
+    }
+}
+
+// commands are enabled and limited by their name, an alias would never match
+fn command_error(name: &str) -> Option<String> {
+    match commands::find(name) {
+        Some(command) if command.name == name => None,
+        Some(command) => Some(format!("{:?} is an alias, use {:?}", name, command.name)),
+        None => Some(format!("unknown command {:?}", name)),
+    }
+}
+
+impl Config {
+    // checks everything that can be checked before connecting, reporting
+    // every problem at once rather than the first
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        if self.networks.len() < 1 {
+            errors.push(String::from("no networks defined"));
+        }
+        if self.http_per_minute == Some(0) {
+            errors.push(String::from("http_per_minute must be at least 1"));
+        }
+
+        let mut names = HashSet::new();
+        for network in &self.networks {
+            let mut network_errors = Vec::new();
+            network.validate(&mut network_errors);
+            if !names.insert(network.name()) {
+                network_errors.push(String::from("network name is used twice, set name to tell them apart"));
+            }
+            for error in network_errors {
+                errors.push(format!("{}: {}", network.name(), error));
+            }
+        }
+
+        if errors.len() > 0 {
+            let msg = format!("invalid configuration:\n  {}", errors.join("\n  "));
+            return Err(Box::new(Error::new(&msg)));
+        }
+        Ok(())
+    }
+}
+
+pub fn load(path: &str) -> Result<Config> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return Err(Box::new(Error::new(&format!("can't read {}: {}", path, e)))),
+    };
+    match toml::from_str(&contents) {
+        Ok(config) => Ok(config),
+        Err(e) => Err(Box::new(Error::new(&format!("{}: {}", path, e)))),
+    }
+}
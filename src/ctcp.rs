@@ -0,0 +1,99 @@
+use chrono::Utc;
+
+use crate::{send, IrcBot, IrcConnection, IrcMessage, Result};
+
+static CLIENTINFO: &str = "ACTION CLIENTINFO PING TIME VERSION";
+
+// a CTCP payload pulled out of a \x01-delimited PRIVMSG
+#[derive(Debug, Clone)]
+pub struct CtcpMessage {
+    pub command: String,
+    pub params: String,
+}
+
+pub fn parse(text: &str) -> Option<CtcpMessage> {
+    if !text.starts_with("\x01") {
+        return None;
+    }
+
+    // some clients leave off the closing \x01
+    let body = text[1..].trim_end_matches("\x01");
+    let mut parts = body.splitn(2, " ");
+    let command = parts.next().unwrap_or("").to_uppercase();
+    if command.len() == 0 {
+        return None;
+    }
+
+    Some(CtcpMessage {
+        command: command,
+        params: String::from(parts.next().unwrap_or("")),
+    })
+}
+
+pub fn encode(command: &str, params: &str) -> String {
+    if params.len() == 0 {
+        return format!("\x01{}\x01", command);
+    }
+    format!("\x01{} {}\x01", command, params)
+}
+
+// cuts text to at most budget bytes without splitting a character
+fn truncate(text: &str, budget: usize) -> &str {
+    let mut end = text.len().min(budget);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn reply(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage, ctcp: &CtcpMessage, params: &str) -> Result<()> {
+    if !bot.ctcp_limiter.try_take(1.0) {
+        log::debug!("dropping ctcp {} from {}, rate limited", ctcp.command, msg.prefix.nick);
+        return Ok(());
+    }
+    // one line, as wrapping would split the \x01 framing
+    let nick = &msg.prefix.nick;
+    let wrapper = encode(&ctcp.command, " ").len();
+    let budget = stream.text_budget("NOTICE", nick).saturating_sub(wrapper);
+    send(stream, &format!("NOTICE {} :{}", nick, encode(&ctcp.command, truncate(params, budget))))
+}
+
+pub fn on_ctcp(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage, ctcp: &CtcpMessage) -> Result<()> {
+    log::debug!("ctcp {} from {}", ctcp.command, msg.prefix.nick);
+
+    match ctcp.command.as_str() {
+        "ACTION" => bot.on_action(stream, msg, ctcp)?,
+        "VERSION" => {
+            let version = format!("rusty {}", env!("CARGO_PKG_VERSION"));
+            reply(bot, stream, msg, ctcp, &version)?;
+        }
+        "PING" => {
+            let params = ctcp.params.clone();
+            reply(bot, stream, msg, ctcp, &params)?;
+        }
+        "TIME" => reply(bot, stream, msg, ctcp, &Utc::now().to_rfc2822())?,
+        "CLIENTINFO" => reply(bot, stream, msg, ctcp, CLIENTINFO)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_on_char_boundary() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 3), "hel");
+        assert_eq!(truncate("héllo", 2), "h");
+        assert_eq!(truncate("héllo", 0), "");
+    }
+
+    #[test]
+    fn keeps_whitespace_in_params() {
+        let ctcp = parse("\x01PING 123  456\x01").unwrap();
+        assert_eq!(ctcp.command, "PING");
+        assert_eq!(encode(&ctcp.command, &ctcp.params), "\x01PING 123  456\x01");
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use regex::{Regex, RegexBuilder};
+use rusqlite::{params, Connection, Result as SQLResult};
+
+use crate::permissions;
+use crate::utils::wildcard_overlap;
+use crate::{Error, IrcBot, IrcMessage, Result};
+
+static CREATE_TABLE_IGNORES: &str = "
+CREATE TABLE IF NOT EXISTS ignores (
+    id INTEGER PRIMARY KEY,
+    network TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    added_by TEXT NOT NULL,
+    added_at DATETIME NOT NULL,
+    expires DATETIME
+);
+";
+
+// what an ignore rule stops the bot from doing for whoever it matches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    All,
+    Commands,
+    Urls,
+    Greetings,
+}
+
+impl Scope {
+    pub fn from_name(name: &str) -> Option<Scope> {
+        match name {
+            "all" => Some(Scope::All),
+            "commands" => Some(Scope::Commands),
+            "urls" => Some(Scope::Urls),
+            "greetings" => Some(Scope::Greetings),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scope::All => write!(f, "all"),
+            Scope::Commands => write!(f, "commands"),
+            Scope::Urls => write!(f, "urls"),
+            Scope::Greetings => write!(f, "greetings"),
+        }
+    }
+}
+
+// /regex/ is matched against nick!user@host ignoring case, anything else is
+// a mask as for owners and grants
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    // None for rules from the config, which aren't stored
+    pub id: Option<i64>,
+    pub pattern: String,
+    regex: Option<Regex>,
+    pub scope: Scope,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl IgnoreRule {
+    pub fn parse(pattern: &str, scope: Scope, expires: Option<DateTime<Utc>>) -> Result<IgnoreRule> {
+        let regex = match pattern.strip_prefix("/").and_then(|p| p.strip_suffix("/")) {
+            Some(regex) if regex.len() > 0 => match RegexBuilder::new(regex).case_insensitive(true).build() {
+                Ok(regex) => Some(regex),
+                // syntax errors draw a diagram over several lines, the last says what's wrong
+                Err(e) => {
+                    let reason = e.to_string().lines().last().unwrap_or("").trim_start_matches("error: ").to_string();
+                    return Err(Box::new(Error::new(&format!("bad regex: {}", reason))));
+                }
+            },
+            _ => None,
+        };
+        if regex.is_none() && pattern.trim().len() == 0 {
+            return Err(Box::new(Error::new("empty ignore pattern")));
+        }
+        Ok(IgnoreRule {
+            id: None,
+            pattern: String::from(pattern),
+            regex: regex,
+            scope: scope,
+            expires: expires,
+        })
+    }
+
+    pub fn expired(&self) -> bool {
+        return self.expires.map_or(false, |expires| expires <= Utc::now());
+    }
+
+    pub fn matches(&self, bot: &IrcBot, msg: &IrcMessage, scope: Scope) -> bool {
+        if (self.scope != Scope::All && self.scope != scope) || self.expired() {
+            return false;
+        }
+        match &self.regex {
+            Some(regex) => {
+                let mask = format!("{}!{}@{}", msg.prefix.nick, msg.prefix.realname, msg.prefix.host);
+                regex.is_match(&mask)
+            }
+            None => bot.matches_mask(&self.pattern, msg),
+        }
+    }
+
+    // whether the rule could match someone covered by an owner or grant mask,
+    // a regex is tried against the mask itself
+    pub fn overlaps(&self, bot: &IrcBot, mask: &str) -> bool {
+        let account = mask.starts_with("$a:");
+        if let Some(regex) = &self.regex {
+            return !account && regex.is_match(mask);
+        }
+        if self.pattern.starts_with("$a:") || self.pattern.contains("!") || self.pattern.contains("@") {
+            return permissions::masks_overlap(bot, &self.pattern, mask);
+        }
+        // a bare nick only ever looks at the nick
+        let casemapping = bot.isupport.casemapping;
+        let nick = mask.split("!").next().unwrap_or("");
+        return !account && wildcard_overlap(&casemapping.fold(&self.pattern), &casemapping.fold(nick));
+    }
+}
+
+pub fn create_table(db: &Connection) -> Result<()> {
+    db.execute(CREATE_TABLE_IGNORES, [])?;
+    Ok(())
+}
+
+// loads this network's stored rules, dropping any that ran out while we were away
+pub fn init(bot: &mut IrcBot) -> Result<()> {
+    prune(bot)?;
+
+    let rows = {
+        let db = &bot.db;
+        let mut stmt = db.prepare("SELECT id, pattern, scope, expires FROM ignores WHERE network=?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![bot.network], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<DateTime<Utc>>>(3)?,
+            ))
+        })?;
+        rows.collect::<SQLResult<Vec<_>>>()?
+    };
+
+    bot.ignores.retain(|rule| rule.id.is_none());
+    for (id, pattern, scope, expires) in rows {
+        let scope = Scope::from_name(&scope).unwrap_or(Scope::All);
+        match IgnoreRule::parse(&pattern, scope, expires) {
+            Ok(mut rule) => {
+                rule.id = Some(id);
+                bot.ignores.push(rule);
+            }
+            Err(e) => log::warn!("skipping ignore rule {}: {}", pattern, e),
+        }
+    }
+    Ok(())
+}
+
+// swaps the rules from the config, leaving the stored ones alone
+pub fn set_config_rules(bot: &mut IrcBot, patterns: &[String]) {
+    bot.ignores.retain(|rule| rule.id.is_some());
+    for pattern in patterns {
+        match IgnoreRule::parse(pattern, Scope::All, None) {
+            Ok(rule) => bot.ignores.push(rule),
+            Err(e) => log::warn!("skipping ignore rule {}: {}", pattern, e),
+        }
+    }
+}
+
+pub fn prune(bot: &mut IrcBot) -> Result<()> {
+    bot.db.execute(
+        "DELETE FROM ignores WHERE network=?1 AND expires IS NOT NULL AND expires <= ?2",
+        params![bot.network, Utc::now()],
+    )?;
+    bot.ignores.retain(|rule| !rule.expired());
+    Ok(())
+}
+
+pub fn add(bot: &mut IrcBot, mut rule: IgnoreRule, added_by: &str) -> Result<()> {
+    {
+        let db = &bot.db;
+        db.execute(
+            "INSERT INTO ignores (network, pattern, scope, added_by, added_at, expires) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![bot.network, rule.pattern, rule.scope.to_string(), added_by, Utc::now(), rule.expires],
+        )?;
+        rule.id = Some(db.last_insert_rowid());
+    }
+    bot.ignores.push(rule);
+    Ok(())
+}
+
+// removes stored rules with this pattern, returning how many there were
+pub fn remove(bot: &mut IrcBot, pattern: &str) -> Result<usize> {
+    let removed = bot.db.execute(
+        "DELETE FROM ignores WHERE network=?1 AND pattern=?2",
+        params![bot.network, pattern],
+    )?;
+    bot.ignores.retain(|rule| rule.id.is_none() || rule.pattern != pattern);
+    Ok(removed)
+}
+
+// 90s, 30m, 1h, 2d or 1w
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    if text.len() < 2 || !text.is_ascii() {
+        return None;
+    }
+    let (count, unit) = text.split_at(text.len() - 1);
+    // chrono panics on durations it can't represent
+    let count: i64 = count.parse().ok().filter(|c| *c > 0 && *c <= 10_000)?;
+    match unit {
+        "s" => Some(Duration::seconds(count)),
+        "m" => Some(Duration::minutes(count)),
+        "h" => Some(Duration::hours(count)),
+        "d" => Some(Duration::days(count)),
+        "w" => Some(Duration::weeks(count)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, message};
+
+    #[test]
+    fn parses_duration_units() {
+        assert_eq!(parse_duration("90s"), Some(Duration::seconds(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_duration("2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_duration("1d"), Some(Duration::days(1)));
+        assert_eq!(parse_duration("1w"), Some(Duration::weeks(1)));
+        assert_eq!(parse_duration("1y"), None);
+        assert_eq!(parse_duration("1M"), None);
+    }
+
+    #[test]
+    fn rejects_durations_out_of_bounds() {
+        assert_eq!(parse_duration("10000w"), Some(Duration::weeks(10_000)));
+        assert_eq!(parse_duration("10001w"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("99999999999999999999w"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("five m"), None);
+        assert_eq!(parse_duration("5é"), None);
+    }
+
+    #[test]
+    fn slashes_make_a_regex() {
+        assert!(IgnoreRule::parse("/^spam/", Scope::All, None).unwrap().regex.is_some());
+        assert!(IgnoreRule::parse("*!*@spam.example", Scope::All, None).unwrap().regex.is_none());
+        assert!(IgnoreRule::parse("spammer", Scope::All, None).unwrap().regex.is_none());
+        // too short to hold a regex, so taken as a mask
+        assert!(IgnoreRule::parse("/", Scope::All, None).unwrap().regex.is_none());
+        assert!(IgnoreRule::parse("//", Scope::All, None).unwrap().regex.is_none());
+        assert!(IgnoreRule::parse("/spam", Scope::All, None).unwrap().regex.is_none());
+    }
+
+    #[test]
+    fn rejects_bad_and_empty_patterns() {
+        let e = IgnoreRule::parse("/(unclosed/", Scope::All, None).unwrap_err();
+        assert!(e.to_string().starts_with("bad regex: "));
+        assert!(!e.to_string().contains("\n"));
+        assert!(IgnoreRule::parse("", Scope::All, None).is_err());
+        assert!(IgnoreRule::parse("  ", Scope::All, None).is_err());
+    }
+
+    #[test]
+    fn matches_by_scope() {
+        let bot = bot();
+        let spammer = message(":Spammer!s@spam.example PRIVMSG #rust :buy now");
+        let rule = IgnoreRule::parse("/^spammer!/", Scope::Urls, None).unwrap();
+        assert!(rule.matches(&bot, &spammer, Scope::Urls));
+        assert!(!rule.matches(&bot, &spammer, Scope::Commands));
+
+        let rule = IgnoreRule::parse("*!*@spam.example", Scope::All, None).unwrap();
+        assert!(rule.matches(&bot, &spammer, Scope::Greetings));
+        let someone = message(":someone!s@example.org PRIVMSG #rust :hi");
+        assert!(!rule.matches(&bot, &someone, Scope::Greetings));
+    }
+
+    #[test]
+    fn expired_rules_match_nobody() {
+        let bot = bot();
+        let spammer = message(":spammer!s@spam.example PRIVMSG #rust :buy now");
+        let rule = IgnoreRule::parse("spammer", Scope::All, Some(Utc::now() - Duration::minutes(1))).unwrap();
+        assert!(rule.expired());
+        assert!(!rule.matches(&bot, &spammer, Scope::All));
+    }
+
+    #[test]
+    fn overlaps_protected_masks() {
+        let bot = bot();
+        let overlaps = |pattern: &str, mask: &str| IgnoreRule::parse(pattern, Scope::All, None).unwrap().overlaps(&bot, mask);
+        assert!(overlaps("*", "boss!b@example.org"));
+        assert!(overlaps("bo*", "boss!b@example.org"));
+        assert!(!overlaps("bob", "boss!b@example.org"));
+        assert!(overlaps("*!*@*", "boss!b@example.org"));
+        assert!(!overlaps("*!*@spam.example", "boss!b@example.org"));
+        assert!(overlaps("/.*/", "boss!b@example.org"));
+        assert!(!overlaps("/^spam/", "boss!b@example.org"));
+        assert!(overlaps("$a:*", "$a:boss"));
+        assert!(!overlaps("*", "$a:boss"));
+        assert!(!overlaps("/.*/", "$a:boss"));
+    }
+}
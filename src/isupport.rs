@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use crate::{nick, IrcBot, IrcConnection, IrcMessage, Result};
+
+// How the server folds case in nicks and channel names.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseMapping {
+    Ascii,
+    // also folds []\~ to {}|^, the default when the server doesn't say
+    Rfc1459,
+    // rfc1459 without ~ and ^
+    StrictRfc1459,
+    // unicode nicks, folded with plain unicode lowercasing
+    Rfc7613,
+}
+
+impl CaseMapping {
+    fn parse(value: &str) -> Option<CaseMapping> {
+        match value {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            "rfc7613" | "precis" => Some(CaseMapping::Rfc7613),
+            _ => None,
+        }
+    }
+
+    pub fn fold(&self, s: &str) -> String {
+        match self {
+            CaseMapping::Ascii => s.to_ascii_lowercase(),
+            CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459 => s
+                .chars()
+                .map(|c| match c {
+                    '[' => '{',
+                    ']' => '}',
+                    '\\' => '|',
+                    '~' if *self == CaseMapping::Rfc1459 => '^',
+                    _ => c.to_ascii_lowercase(),
+                })
+                .collect(),
+            CaseMapping::Rfc7613 => s.to_lowercase(),
+        }
+    }
+
+    pub fn equals(&self, a: &str, b: &str) -> bool {
+        return self.fold(a) == self.fold(b);
+    }
+}
+
+// What the server advertised in 005 RPL_ISUPPORT, with RFC defaults until then.
+#[derive(Debug, Clone)]
+pub struct ISupport {
+    pub casemapping: CaseMapping,
+    // (mode, symbol) pairs, highest first
+    pub prefixes: Vec<(char, char)>,
+    pub nicklen: Option<usize>,
+    // command -> max targets, None for unlimited
+    pub targmax: HashMap<String, Option<usize>>,
+    pub linelen: Option<usize>,
+    // list modes, modes that always take a parameter, modes that take one when set, flags
+    pub chanmodes: [String; 4],
+}
+
+impl ISupport {
+    pub fn new() -> ISupport {
+        return ISupport {
+            casemapping: CaseMapping::Rfc1459,
+            prefixes: vec![('o', '@'), ('v', '+')],
+            nicklen: None,
+            targmax: HashMap::new(),
+            linelen: None,
+            chanmodes: [
+                String::from("beI"),
+                String::from("k"),
+                String::from("l"),
+                String::new(),
+            ],
+        };
+    }
+
+    // whether a non-prefix channel mode consumes a parameter
+    pub fn takes_param(&self, mode: char, enable: bool) -> bool {
+        if self.chanmodes[0].contains(mode) || self.chanmodes[1].contains(mode) {
+            return true;
+        }
+        self.chanmodes[2].contains(mode) && enable
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "CASEMAPPING" => match CaseMapping::parse(value) {
+                Some(casemapping) => self.casemapping = casemapping,
+                None => log::warn!("unknown casemapping {}, keeping {:?}", value, self.casemapping),
+            },
+            // PREFIX=(ov)@+
+            "PREFIX" => {
+                let (modes, symbols) = match value.strip_prefix("(").and_then(|v| v.split_once(")")) {
+                    Some(split) => split,
+                    None => ("", ""),
+                };
+                self.prefixes = modes.chars().zip(symbols.chars()).collect();
+            }
+            "NICKLEN" => self.nicklen = value.parse().ok(),
+            "LINELEN" => self.linelen = value.parse().ok(),
+            // TARGMAX=PRIVMSG:4,NOTICE:4,JOIN:
+            "TARGMAX" => {
+                for entry in value.split(",") {
+                    let mut parts = entry.splitn(2, ":");
+                    let command = parts.next().unwrap_or("").to_uppercase();
+                    let max = parts.next().and_then(|max| max.parse().ok());
+                    self.targmax.insert(command, max);
+                }
+            }
+            // CHANMODES=beI,k,l,imnpst
+            "CHANMODES" => {
+                for (i, modes) in value.splitn(4, ",").enumerate() {
+                    self.chanmodes[i] = String::from(modes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn unset(&mut self, key: &str) {
+        let defaults = ISupport::new();
+        match key {
+            "CASEMAPPING" => self.casemapping = defaults.casemapping,
+            "PREFIX" => self.prefixes = defaults.prefixes,
+            "NICKLEN" => self.nicklen = None,
+            "LINELEN" => self.linelen = None,
+            "TARGMAX" => self.targmax.clear(),
+            "CHANMODES" => self.chanmodes = defaults.chanmodes,
+            _ => {}
+        }
+    }
+}
+
+// 005 RPL_ISUPPORT: <me> <token[=value]>... :are supported by this server
+pub fn on_isupport(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if msg.args.len() < 3 {
+        return Ok(());
+    }
+
+    let casemapping = bot.isupport.casemapping;
+    for token in &msg.args[1..msg.args.len() - 1] {
+        match token.strip_prefix("-") {
+            Some(key) => bot.isupport.unset(key),
+            None => {
+                let mut parts = token.splitn(2, "=");
+                let key = parts.next().unwrap_or("");
+                bot.isupport.set(key, parts.next().unwrap_or(""));
+            }
+        }
+    }
+
+    if let Some(linelen) = bot.isupport.linelen {
+        stream.line_length = linelen;
+    }
+    bot.members.apply_isupport(&bot.isupport);
+    if bot.isupport.casemapping != casemapping {
+        let refolded = bot.refold_nick_keys()?;
+        if refolded > 0 {
+            log::info!("refolded {} nicks for casemapping {:?}", refolded, bot.isupport.casemapping);
+        }
+    }
+    nick::on_isupport(bot, stream, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_folds_letters_only() {
+        assert_eq!(CaseMapping::Ascii.fold("Nick[]\\~ÀÉ"), "nick[]\\~ÀÉ");
+    }
+
+    #[test]
+    fn rfc1459_folds_brackets_and_tilde() {
+        assert_eq!(CaseMapping::Rfc1459.fold("Nick[]\\~"), "nick{}|^");
+        assert!(CaseMapping::Rfc1459.equals("foo[away]", "FOO{AWAY}"));
+        assert!(CaseMapping::Rfc1459.equals("a~b", "a^b"));
+    }
+
+    #[test]
+    fn strict_rfc1459_leaves_tilde() {
+        assert_eq!(CaseMapping::StrictRfc1459.fold("Nick[]\\~"), "nick{}|~");
+        assert!(CaseMapping::StrictRfc1459.equals("foo[away]", "FOO{AWAY}"));
+        assert!(!CaseMapping::StrictRfc1459.equals("a~b", "a^b"));
+    }
+
+    #[test]
+    fn rfc7613_folds_unicode() {
+        assert_eq!(CaseMapping::Rfc7613.fold("ÀÉNick[]"), "àénick[]");
+        assert!(!CaseMapping::Rfc7613.equals("[a]", "{a}"));
+    }
+
+    #[test]
+    fn parses_casemapping_names() {
+        assert_eq!(CaseMapping::parse("ascii"), Some(CaseMapping::Ascii));
+        assert_eq!(CaseMapping::parse("rfc1459"), Some(CaseMapping::Rfc1459));
+        assert_eq!(CaseMapping::parse("strict-rfc1459"), Some(CaseMapping::StrictRfc1459));
+        assert_eq!(CaseMapping::parse("rfc7613"), Some(CaseMapping::Rfc7613));
+        assert_eq!(CaseMapping::parse("precis"), Some(CaseMapping::Rfc7613));
+        assert_eq!(CaseMapping::parse("utf-8"), None);
+    }
+
+    #[test]
+    fn set_and_unset_casemapping() {
+        let mut isupport = ISupport::new();
+        isupport.set("CASEMAPPING", "ascii");
+        assert_eq!(isupport.casemapping, CaseMapping::Ascii);
+        isupport.set("CASEMAPPING", "bogus");
+        assert_eq!(isupport.casemapping, CaseMapping::Ascii);
+        isupport.unset("CASEMAPPING");
+        assert_eq!(isupport.casemapping, CaseMapping::Rfc1459);
+    }
+}
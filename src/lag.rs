@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use crate::reconnect::Disconnect;
+use crate::{send, IrcBot, IrcConnection, IrcMessage, Result};
+
+pub const DEFAULT_PING_INTERVAL: u64 = 60;
+pub const DEFAULT_PING_TIMEOUT: u64 = 240;
+
+// Pings the server ourselves so a silently dead connection is noticed, and
+// keeps the last measured round trip for !lag.
+#[derive(Debug)]
+pub struct LagMonitor {
+    interval: Duration,
+    timeout: Duration,
+    last_activity: Instant,
+    last_ping: Instant,
+    pending: Option<String>,
+    lag: Option<Duration>,
+}
+
+impl LagMonitor {
+    pub fn new(interval: Duration, timeout: Duration) -> LagMonitor {
+        return LagMonitor {
+            interval: interval,
+            timeout: timeout,
+            last_activity: Instant::now(),
+            last_ping: Instant::now(),
+            pending: None,
+            lag: None,
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.last_activity = Instant::now();
+        self.last_ping = Instant::now();
+        self.pending = None;
+        self.lag = None;
+    }
+
+    // anything read from the server proves the connection is alive
+    pub fn saw_activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    pub fn lag(&self) -> Option<Duration> {
+        // an unanswered ping is at least as laggy as it is old
+        match &self.pending {
+            Some(_) if self.last_ping.elapsed() > self.lag.unwrap_or_default() => {
+                Some(self.last_ping.elapsed())
+            }
+            _ => self.lag,
+        }
+    }
+}
+
+pub fn on_pong(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let token = msg.args.last().cloned().unwrap_or_default();
+    if bot.lag.pending.as_ref() == Some(&token) {
+        bot.lag.pending = None;
+        bot.lag.lag = Some(bot.lag.last_ping.elapsed());
+        log::debug!("lag is {:?}", bot.lag.lag);
+    }
+    Ok(())
+}
+
+pub fn tick(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    let idle = bot.lag.last_activity.elapsed();
+    if idle > bot.lag.timeout {
+        bot.abort(Disconnect::Timeout(format!("no data for {}s", idle.as_secs())), false);
+        return Ok(());
+    }
+
+    if !bot.registered || bot.lag.pending.is_some() || bot.lag.last_ping.elapsed() < bot.lag.interval {
+        return Ok(());
+    }
+
+    let token = format!("rusty-{}", chrono::Utc::now().timestamp_millis());
+    send(stream, &format!("PING :{}", token))?;
+    bot.lag.pending = Some(token);
+    bot.lag.last_ping = Instant::now();
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::io;
+
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+// tags may take 8191 bytes and the rest of the line 512, each counting the
+// space or CRLF that ends it
+pub const MAX_LINE_LENGTH: usize = 8191 + 512;
+
+// Frames a byte stream into IRC lines.  Bytes are kept between reads until a
+// full line is seen so lines and utf-8 sequences split across reads survive.
+#[derive(Debug)]
+pub struct LineReader {
+    buffer: Vec<u8>,
+    lines: VecDeque<String>,
+    max_length: usize,
+    discarding: bool,
+}
+
+impl LineReader {
+    pub fn new(max_length: usize) -> LineReader {
+        return LineReader {
+            buffer: Vec::new(),
+            lines: VecDeque::new(),
+            max_length: max_length,
+            discarding: false,
+        };
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        let mut start = 0;
+        while let Some(offset) = data[start..].iter().position(|b| *b == b'\n') {
+            let end = start + offset;
+            self.push(&data[start..end]);
+            self.finish_line();
+            start = end + 1;
+        }
+        self.push(&data[start..]);
+    }
+
+    pub fn next_line(&mut self) -> Option<String> {
+        return self.lines.pop_front();
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.discarding {
+            return;
+        }
+
+        if self.buffer.len() + data.len() > self.max_length {
+            log::warn!("dropping line longer than {} bytes", self.max_length);
+            self.buffer.clear();
+            self.discarding = true;
+            return;
+        }
+
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn finish_line(&mut self) {
+        if self.discarding {
+            self.discarding = false;
+            return;
+        }
+
+        if self.buffer.last() == Some(&b'\r') {
+            self.buffer.pop();
+        }
+
+        if self.buffer.len() > 0 {
+            let line = String::from_utf8_lossy(&self.buffer).to_string();
+            self.lines.push_back(line);
+        }
+        self.buffer.clear();
+    }
+}
+
+// lets FramedRead drive the reader straight off a socket
+impl Decoder for LineReader {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<String>> {
+        if src.len() > 0 {
+            let data = src.split_to(src.len());
+            self.feed(&data);
+        }
+        Ok(self.next_line())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(reader: &mut LineReader) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line() {
+            lines.push(line);
+        }
+        return lines;
+    }
+
+    #[test]
+    fn splits_on_crlf_and_lf() {
+        let mut reader = LineReader::new(MAX_LINE_LENGTH);
+        reader.feed(b"PING :a\r\nPING :b\nPING :c\r\n");
+        assert_eq!(drain(&mut reader), vec!["PING :a", "PING :b", "PING :c"]);
+    }
+
+    #[test]
+    fn keeps_partial_line_between_reads() {
+        let mut reader = LineReader::new(MAX_LINE_LENGTH);
+        reader.feed(b":server 353 rusty = #chan :al");
+        assert!(reader.next_line().is_none());
+        reader.feed(b"ice bob\r");
+        assert!(reader.next_line().is_none());
+        reader.feed(b"\n:server 366 rusty #chan :End");
+        assert_eq!(drain(&mut reader), vec![":server 353 rusty = #chan :alice bob"]);
+        reader.feed(b"\r\n");
+        assert_eq!(drain(&mut reader), vec![":server 366 rusty #chan :End"]);
+    }
+
+    #[test]
+    fn keeps_split_utf8_sequence() {
+        let mut reader = LineReader::new(MAX_LINE_LENGTH);
+        let line = "PRIVMSG #chan :❤️\r\n".as_bytes();
+        for byte in line {
+            reader.feed(&[*byte]);
+        }
+        assert_eq!(drain(&mut reader), vec!["PRIVMSG #chan :❤️"]);
+    }
+
+    #[test]
+    fn skips_empty_lines() {
+        let mut reader = LineReader::new(MAX_LINE_LENGTH);
+        reader.feed(b"\r\n\nPING :a\r\n\r\nPING :b\r\n");
+        assert_eq!(drain(&mut reader), vec!["PING :a", "PING :b"]);
+    }
+
+    #[test]
+    fn keeps_longest_tagged_line() {
+        let tags = format!("@a={}", "x".repeat(8191 - 4));
+        let rest = format!("PRIVMSG #chan :{}", "y".repeat(512 - 15 - 2));
+        let line = format!("{} {}", tags, rest);
+        assert_eq!(line.len() + 2, MAX_LINE_LENGTH);
+
+        let mut reader = LineReader::new(MAX_LINE_LENGTH);
+        reader.feed(format!("{}\r\n", line).as_bytes());
+        assert_eq!(drain(&mut reader), vec![line.clone()]);
+
+        reader.feed(format!("{}zz\r\nPING :after\r\n", line).as_bytes());
+        assert_eq!(drain(&mut reader), vec!["PING :after"]);
+    }
+
+    #[test]
+    fn drops_overlong_lines() {
+        let mut reader = LineReader::new(16);
+        reader.feed(b"PING :short\r\nPRIVMSG #chan :this is");
+        reader.feed(b" far too long\r\nPING :after\r\n");
+        assert_eq!(drain(&mut reader), vec!["PING :short", "PING :after"]);
+    }
+}
@@ -1,24 +1,19 @@
 extern crate chrono;
 extern crate clap;
-extern crate ctrlc;
 extern crate lazy_static;
 extern crate linkify;
 extern crate regex;
 extern crate rustls;
 
-use std::convert::TryInto;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
-use std::io::prelude::*;
-use std::io::ErrorKind;
-use std::net::TcpStream;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration as Timeout;
-
-use chrono::{DateTime, Duration, Utc};
-use clap::{App, Arg};
+use std::process;
+use std::sync::{Arc, MutexGuard};
+use std::time::{Duration as Timeout, Instant};
+
+use chrono::{DateTime, Utc};
+use clap::{App, Arg, ArgMatches};
 use data_encoding::HEXLOWER;
 use env_logger;
 use linkify::{LinkFinder, LinkKind};
@@ -27,14 +22,57 @@ use rand::seq::IteratorRandom;
 use regex::Regex;
 use ring::digest::{Context, SHA256};
 use rusqlite::{params, Connection, Result as SQLResult, OptionalExtension};
-use rustls::client::HandshakeSignatureValid;
-use rustls::{ClientConfig, ClientConnection, Stream};
+use tokio::io::split;
+use tokio::signal;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{interval, sleep};
 
-type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
-type IrcConnection<'a> = Stream<'a, ClientConnection, TcpStream>;
+type Result<T> = std::result::Result<T, Box<dyn error::Error + Send + Sync>>;
 
+mod caps;
+mod channels;
 mod commands;
+mod config;
+mod ctcp;
+mod ignore;
+mod isupport;
+mod lag;
+mod linereader;
+mod members;
+mod nick;
+mod permissions;
+mod queue;
+mod ratelimit;
+mod reconnect;
+mod reload;
+mod sasl;
+mod split;
+#[cfg(test)]
+mod testutil;
+mod tls;
+mod transport;
 mod utils;
+mod workers;
+
+use caps::{Capabilities, DEFAULT_CAPS};
+use channels::ChannelConfig;
+use commands::Level;
+use config::{ApiKeys, Config, NetworkConfig};
+use ignore::{IgnoreRule, Scope};
+use isupport::{CaseMapping, ISupport};
+use lag::{LagMonitor, DEFAULT_PING_INTERVAL, DEFAULT_PING_TIMEOUT};
+use members::Members;
+use permissions::Grant;
+use ctcp::CtcpMessage;
+use queue::TokenBucket;
+use ratelimit::{HttpBudget, RateLimiter, DEFAULT_HTTP_PER_MINUTE};
+use reconnect::{Disconnect, Reconnect};
+use reload::{Reload, ReloadRequest};
+use sasl::Mechanism;
+use split::{split_message, DEFAULT_MAX_LINES};
+use transport::Outgoing;
+use utils::wildcard_match;
+use workers::{WorkerPool, DEFAULT_WORKERS};
 
 #[derive(Debug, Clone)]
 struct Error {
@@ -61,36 +99,138 @@ impl error::Error for Error {
     }
 }
 
-fn send(s: &mut IrcConnection, msg: &String) -> Result<()> {
-    let mut out = String::from(msg);
-    out.push_str("\r\n");
+// CTCP replies allowed in a burst, then one every 5 seconds
+const CTCP_BURST: f64 = 3.0;
+const CTCP_REFILL_PER_SEC: f64 = 0.2;
 
-    // log::debug!("out: {:?}", out);
+// how often timers (nick regain, lag pings) are checked
+const TICK: Timeout = Timeout::from_millis(250);
+// lines read ahead of the handlers before the reader waits
+const LINE_BACKLOG: usize = 256;
 
-    s.write(out.as_bytes())?;
-    Ok(())
+// worst case ! + USERLEN + @ + HOSTLEN when we don't know our own prefix yet
+const MAX_USER_HOST_LEN: usize = 1 + 10 + 1 + 63;
+// longer messages are cut short before they're stored for !seen
+const MAX_SEEN_MESSAGE_LEN: usize = 300;
+
+// A handle on the writer task.  Clones can be moved into command tasks, the
+// nick and prefix they carry are only used to size replies.
+#[derive(Debug, Clone)]
+pub struct IrcConnection {
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+
+    nick: String,
+    self_prefix: Option<String>,
+    line_length: usize,
+    max_lines: usize,
 }
 
-fn ident(s: &mut IrcConnection, nick: &String) -> Result<()> {
+impl IrcConnection {
+    fn new(outgoing: mpsc::UnboundedSender<Outgoing>, nick: &String, max_lines: usize) -> IrcConnection {
+        return IrcConnection {
+            outgoing: outgoing,
+            nick: nick.clone(),
+            self_prefix: None,
+            line_length: 512,
+            max_lines: max_lines,
+        };
+    }
+
+    // bytes left for text once the server relays `:nick!user@host COMMAND target :text`
+    fn text_budget(&self, command: &str, target: &str) -> usize {
+        let prefix_len = match &self.self_prefix {
+            Some(prefix) => prefix.len(),
+            None => self.nick.len() + MAX_USER_HOST_LEN,
+        };
+        let overhead = 1 + prefix_len + 1 + command.len() + 1 + target.len() + 2 + 2;
+        return self.line_length.saturating_sub(overhead).max(1);
+    }
+
+    fn push(&self, message: Outgoing) -> Result<()> {
+        if self.outgoing.send(message).is_err() {
+            return Err(Box::new(Error::new("connection closed")));
+        }
+        Ok(())
+    }
+
+    // writes everything queued, ignoring the flood limiter
+    async fn flush_all(&self) -> Result<()> {
+        let (done, flushed) = oneshot::channel();
+        self.push(Outgoing::Flush(done))?;
+        flushed.await?;
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        let (done, closed) = oneshot::channel();
+        self.push(Outgoing::Close(done))?;
+        closed.await?;
+        Ok(())
+    }
+}
+
+fn send(s: &mut IrcConnection, msg: &String) -> Result<()> {
+    s.push(Outgoing::Line(msg.clone()))
+}
+
+fn send_tagged(s: &mut IrcConnection, tags: &HashMap<String, String>, msg: &String) -> Result<()> {
+    if tags.is_empty() {
+        return send(s, msg);
+    }
+    send(s, &format!("{} {}", format_tags(tags), msg))
+}
+
+fn ident(s: &mut IrcConnection, nick: &String, realname: &String) -> Result<()> {
     send(s, &format!("NICK {}", nick))?;
-    send(s, &format!("USER {} 0 * :{}", nick, nick))?;
+    send(s, &format!("USER {} 0 * :{}", nick, realname))?;
     Ok(())
 }
 
-fn join(s: &mut IrcConnection, channel: &String) -> Result<()> {
-    send(s, &format!("JOIN :{}", channel))?;
+fn join(s: &mut IrcConnection, channel: &String, key: &Option<String>) -> Result<()> {
+    match key {
+        Some(key) => send(s, &format!("JOIN {} :{}", channel, key))?,
+        None => send(s, &format!("JOIN :{}", channel))?,
+    }
     Ok(())
 }
 
 pub fn say(stream: &mut IrcConnection, target: &String, what: &String) -> Result<()> {
-    let mut s = what.replace("\n", "  ");
-    if s.len() > 1000 {
-        s = String::from(&s[..1000]);
-        s.push_str("...");
+    let budget = stream.text_budget("PRIVMSG", target);
+    for line in split_message(what, budget, stream.max_lines) {
+        send(stream, &format!("PRIVMSG {} :{}", target, line))?;
     }
-    let mut out = format!("PRIVMSG {} :", target);
-    out.push_str(&s);
-    send(stream, &out)?;
+    Ok(())
+}
+
+// like say() but with tags on every line, such as +draft/reply from reply_tags()
+pub fn say_tagged(stream: &mut IrcConnection, tags: &HashMap<String, String>, target: &String, what: &String) -> Result<()> {
+    let budget = stream.text_budget("PRIVMSG", target);
+    for line in split_message(what, budget, stream.max_lines) {
+        send_tagged(stream, tags, &format!("PRIVMSG {} :{}", target, line))?;
+    }
+    Ok(())
+}
+
+pub fn notice(stream: &mut IrcConnection, target: &String, what: &String) -> Result<()> {
+    let budget = stream.text_budget("NOTICE", target);
+    for line in split_message(what, budget, stream.max_lines) {
+        send(stream, &format!("NOTICE {} :{}", target, line))?;
+    }
+    Ok(())
+}
+
+// like say() but as an emote, each line is its own ACTION
+pub fn action(stream: &mut IrcConnection, target: &String, what: &String) -> Result<()> {
+    let wrapper = ctcp::encode("ACTION", "").len() + 1;
+    let budget = stream.text_budget("PRIVMSG", target).saturating_sub(wrapper).max(1);
+    for line in split_message(what, budget, stream.max_lines) {
+        send(stream, &format!("PRIVMSG {} :{}", target, ctcp::encode("ACTION", &line)))?;
+    }
+    Ok(())
+}
+
+fn part(s: &mut IrcConnection, channel: &String, reason: &String) -> Result<()> {
+    send(s, &format!("PART {} :{}", channel, reason))?;
     Ok(())
 }
 
@@ -133,18 +273,106 @@ fn parse_prefix(s: &String) -> IrcPrefix {
     }
 }
 
+fn unescape_tag_value(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            ';' => out.push_str("\\:"),
+            ' ' => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn parse_tags(s: &str) -> HashMap<String, String> {
+    let mut tags = HashMap::new();
+    for tag in s.split(";") {
+        if tag.len() == 0 {
+            continue;
+        }
+        let mut parts = tag.splitn(2, "=");
+        let key = String::from(parts.next().unwrap_or(""));
+        let value = unescape_tag_value(parts.next().unwrap_or(""));
+        tags.insert(key, value);
+    }
+    tags
+}
+
+fn format_tags(tags: &HashMap<String, String>) -> String {
+    let mut keys: Vec<&String> = tags.keys().collect();
+    keys.sort();
+
+    let parts: Vec<String> = keys
+        .iter()
+        .map(|key| {
+            let value = &tags[*key];
+            if value.len() == 0 {
+                key.to_string()
+            } else {
+                format!("{}={}", key, escape_tag_value(value))
+            }
+        })
+        .collect();
+    format!("@{}", parts.join(";"))
+}
+
 #[derive(Debug, Clone)]
 pub struct IrcMessage {
+    tags: HashMap<String, String>,
     prefix: IrcPrefix,
     command: String,
     args: Vec<String>,
 }
 
-fn parse_message(s: &mut String) -> IrcMessage {
+impl IrcMessage {
+    // server-time when the server sent one, otherwise the time we saw it
+    fn time(&self) -> DateTime<Utc> {
+        if let Some(time) = self.tags.get("time") {
+            if let Ok(parsed) = DateTime::parse_from_rfc3339(time) {
+                return parsed.with_timezone(&Utc);
+            }
+        }
+        Utc::now()
+    }
+}
+
+// None for a line with no command, such as a blank or tags-only one
+fn parse_message(s: &mut String) -> Option<IrcMessage> {
+    let mut tags = HashMap::new();
     let mut prefix = String::new();
     let mut args: Vec<String> = Vec::new();
-    let mut idx = 0;
 
+    if s.starts_with("@") {
+        let tags_end = s.find(" ").unwrap_or(s.len());
+        tags = parse_tags(&s[1..tags_end]);
+        *s = String::from(s[tags_end..].trim_start());
+    }
+
+    let mut idx = 0;
     if s.starts_with(":") {
         idx = s.find(" ").unwrap_or(s.len());
         prefix = String::from(&s[1..idx]);
@@ -164,17 +392,32 @@ fn parse_message(s: &mut String) -> IrcMessage {
         }
     }
 
+    if args.len() == 0 {
+        return None;
+    }
     let command = args.remove(0);
-    IrcMessage {
+    Some(IrcMessage {
+        tags: tags,
         prefix: parse_prefix(&prefix),
         command: command,
         args: args,
-    }
+    })
 }
 
-fn on_welcome(bot: &mut IrcBot, stream: &mut IrcConnection, _msg: &IrcMessage) -> Result<()> {
-    join(stream, &bot.channel)?;
-    say(stream, &bot.channel, &random_greeting())?;
+fn on_welcome(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    // servers without CAP support never answer LS
+    bot.caps.finish();
+    if bot.sasl.is_some() && !bot.has_cap("sasl") {
+        return sasl::fail(bot, stream, "the server doesn't support sasl");
+    }
+    nick::on_registered(bot, stream, msg)?;
+    for channel in &bot.channels {
+        join(stream, &channel.name, &channel.key)?;
+        if channel.greetings {
+            say(stream, &channel.name, &bot.random_greeting())?;
+            bot.last_greet.insert(channel.name.clone(), Utc::now());
+        }
+    }
     Ok(())
 }
 
@@ -194,29 +437,83 @@ fn on_command(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) ->
         } else {
         rest = String::from("");
     }
-    bot.dispatch(stream, &msg, &command, &rest)?;
+    commands::dispatch(bot, stream, &msg, &command, &rest)?;
     Ok(())
 }
 
 fn on_privmsg(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
-    if msg.args[0] != bot.channel {
+    // only what they say is ignored, joins and nick changes are still tracked
+    if bot.is_ignored(msg, Scope::All) {
+        log::debug!("ignored: {:?}", msg);
+        return Ok(());
+    }
+
+    if let Some(ctcp) = ctcp::parse(&msg.args[1]) {
+        return ctcp::on_ctcp(bot, stream, msg, &ctcp);
+    }
+
+    let channel = match bot.channel(&msg.args[0]) {
+        Some(channel) => channel.clone(),
+        None => return Ok(()),
+    };
+
+    // with echo-message our own lines come back to us
+    if bot.has_cap("echo-message") && bot.is_me(&msg.prefix.nick) {
         return Ok(());
     }
 
     let ident = bot.ensure_ident(msg)?;
+    bot.record_message(&ident, msg, &msg.args[1])?;
 
-    let mut prefix = String::from(&bot.nick);
+    let mut prefix = String::from(&bot.current_nick);
     prefix.push_str(": ");
     if msg.args[1].starts_with(&prefix) {
-        say(stream, &bot.channel, &random_greeting())?;
+        if channel.greetings && !bot.is_ignored(msg, Scope::Greetings) {
+            say(stream, &channel.name, &bot.random_greeting())?;
+        }
     } else if msg.args[1].starts_with("!") {
-        on_command(bot, stream, msg)?;
+        if !bot.is_ignored(msg, Scope::Commands) {
+            on_command(bot, stream, msg)?;
+        }
     } else {
-        bot.see(stream, &msg)?;
+        bot.see(stream, &msg, &msg.args[1])?;
     }
     Ok(())
 }
 
+fn on_join(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if bot.is_me(&msg.prefix.nick) {
+        stream.self_prefix = Some(format!("{}!{}@{}", msg.prefix.nick, msg.prefix.realname, msg.prefix.host));
+    } else if let Some(channel) = msg.args.get(0) {
+        bot.record_event(msg, &format!("joining {}", channel), Some(channel))?;
+    }
+    members::on_join(bot, stream, msg)
+}
+
+fn on_part(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if let Some(channel) = msg.args.get(0) {
+        if !bot.is_me(&msg.prefix.nick) {
+            bot.record_event(msg, &format!("leaving {}", channel), Some(channel))?;
+        }
+    }
+    members::on_part(bot, stream, msg)
+}
+
+fn on_quit(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let event = match msg.args.get(0) {
+        Some(reason) if reason.len() > 0 => format!("quitting ({})", reason),
+        _ => String::from("quitting"),
+    };
+    bot.record_event(msg, &event, None)?;
+    members::on_quit(bot, stream, msg)
+}
+
+fn on_nick(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    nick::on_nick(bot, stream, msg)?;
+    members::on_nick(bot, stream, msg)?;
+    bot.rename_ident(msg)
+}
+
 fn on_ping(_bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
     let mut out = String::from("PONG :");
     out.push_str(&msg.args[0]);
@@ -227,13 +524,6 @@ fn on_ping(_bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> R
 type CallbackHandler =
     fn(bot: &mut IrcBot, stream: &mut IrcConnection, message: &IrcMessage) -> Result<()>;
 
-type Command = fn(
-    bot: &mut IrcBot,
-    stream: &mut IrcConnection,
-    message: &IrcMessage,
-    rest: &String,
-) -> Result<()>;
-
 #[derive(Debug)]
 pub struct SeenUrl {
     id: i64,
@@ -253,102 +543,343 @@ pub struct Ident {
 
 #[derive(Debug)]
 pub struct IrcBot {
+    // the network's name in the config, host is the server we're on
+    network: String,
     host: String,
+    // the nick we want, current_nick is the one we have
     nick: String,
-    channel: String,
-    ignore: Option<Vec<String>>,
+    current_nick: String,
+    alt_nicks: Vec<String>,
+    nick_attempt: usize,
+    registered: bool,
+    supports_monitor: bool,
+    last_ison: Option<Instant>,
+
+    channels: Vec<ChannelConfig>,
+    ignores: Vec<IgnoreRule>,
+    // masks allowed to run owner commands
+    owners: Vec<String>,
+    grants: Vec<Grant>,
+    realname: String,
+    greetings: Vec<String>,
+    // None enables every command
+    commands: Option<Vec<String>>,
+    api_keys: ApiKeys,
+    caps: Capabilities,
+    sasl: Option<Mechanism>,
+    sasl_failures: u32,
+    reload: Option<mpsc::UnboundedSender<ReloadRequest>>,
+
+    // set by handlers to drop the connection, fatal stops reconnecting
+    abort: Option<Disconnect>,
+    fatal: bool,
 
     db: Connection,
-
-    last_greet: DateTime<Utc>,
+    workers: WorkerPool,
+
+    last_greet: HashMap<String, DateTime<Utc>>,
+    ctcp_limiter: TokenBucket,
+    limiter: RateLimiter,
+    http_budget: HttpBudget,
+    lag: LagMonitor,
+    isupport: ISupport,
+    members: Members,
 }
 
 static CREATE_TABLE_SEEN_IDENTS: &str = "
 CREATE TABLE IF NOT EXISTS seen_idents (
     id INTEGER PRIMARY KEY,
+    network TEXT,
     nick TEXT,
+    nick_key TEXT,
     realname TEXT,
     host TEXT,
-    last_seen DATETIME NOT NULL
+    last_seen DATETIME NOT NULL,
+    last_event TEXT,
+    last_event_channel TEXT,
+    last_channel TEXT,
+    last_message TEXT,
+    last_spoke DATETIME
 );
+CREATE INDEX IF NOT EXISTS seen_idents_network_nick_key ON seen_idents (network, nick_key);
+";
+
+// networks sharing a database keep separate idents
+static MIGRATE_SEEN_IDENTS_NETWORK: &str = "
+ALTER TABLE seen_idents ADD COLUMN network TEXT;
+DROP INDEX IF EXISTS seen_idents_nick_key;
+";
+
+// what they last did and said, for !seen
+static MIGRATE_SEEN_IDENTS_ACTIVITY: &str = "
+ALTER TABLE seen_idents ADD COLUMN last_event TEXT;
+ALTER TABLE seen_idents ADD COLUMN last_channel TEXT;
+ALTER TABLE seen_idents ADD COLUMN last_message TEXT;
+ALTER TABLE seen_idents ADD COLUMN last_spoke DATETIME;
+";
+
+// where joins and parts happened, so !seen elsewhere can leave them out
+static MIGRATE_SEEN_IDENTS_EVENT_CHANNEL: &str = "
+ALTER TABLE seen_idents ADD COLUMN last_event_channel TEXT;
+UPDATE seen_idents SET last_event_channel=substr(last_event, 9)
+    WHERE last_event LIKE 'joining %' OR last_event LIKE 'leaving %';
 ";
 
 static CREATE_TABLE_SEEN_URLS: &str = "
 CREATE TABLE IF NOT EXISTS seen_urls (
     id INTEGER PRIMARY KEY,
+    network TEXT NOT NULL,
+    channel TEXT NOT NULL,
     owner_id INTEGER NOT NULL,
-    url_hash TEXT NOT NULL UNIQUE,
+    url_hash TEXT NOT NULL,
     count INTEGER NOT NULL DEFAULT 1,
-    first_seen DATETIME NOT NULL
+    first_seen DATETIME NOT NULL,
+    UNIQUE (network, channel, url_hash)
 );
 ";
 
+// seen_urls predates multi-channel support, rebuild it with a channel column
+static MIGRATE_SEEN_URLS_CHANNEL: &str = "
+CREATE TABLE seen_urls_new (
+    id INTEGER PRIMARY KEY,
+    channel TEXT NOT NULL,
+    owner_id INTEGER NOT NULL,
+    url_hash TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 1,
+    first_seen DATETIME NOT NULL,
+    UNIQUE (channel, url_hash)
+);
+INSERT INTO seen_urls_new (id, channel, owner_id, url_hash, count, first_seen)
+    SELECT id, '', owner_id, url_hash, count, first_seen FROM seen_urls;
+DROP TABLE seen_urls;
+ALTER TABLE seen_urls_new RENAME TO seen_urls;
+";
+
+// channels on different networks sharing a database keep separate reposts
+static MIGRATE_SEEN_URLS_NETWORK: &str = "
+CREATE TABLE seen_urls_new (
+    id INTEGER PRIMARY KEY,
+    network TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    owner_id INTEGER NOT NULL,
+    url_hash TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 1,
+    first_seen DATETIME NOT NULL,
+    UNIQUE (network, channel, url_hash)
+);
+INSERT INTO seen_urls_new (id, network, channel, owner_id, url_hash, count, first_seen)
+    SELECT id, '', channel, owner_id, url_hash, count, first_seen FROM seen_urls;
+DROP TABLE seen_urls;
+ALTER TABLE seen_urls_new RENAME TO seen_urls;
+";
 
 static GREETINGS: &[&str] = &[
     "hi", "high", "hello", "sirs", "pals", "buddies", "friends", "amigos", "compadres", "mates", "chums", "confidants", "brothers"
 ];
 
-fn random_greeting() -> String {
-    let mut rng = rand::thread_rng();
-    return String::from(*GREETINGS.iter().choose(&mut rng).unwrap());
+fn default_greetings() -> Vec<String> {
+    return GREETINGS.iter().map(|s| s.to_string()).collect();
 }
 
 
 impl IrcBot {
-    fn new(host: String, nick: String, channel: String, db: Connection) -> IrcBot {
+    fn new(host: String, nick: String, channels: Vec<ChannelConfig>, db: Connection) -> IrcBot {
         return IrcBot {
+            network: host.clone(),
             host: host,
+            current_nick: nick.clone(),
+            realname: nick.clone(),
             nick: nick,
-            channel: channel,
-            ignore: None,
+            alt_nicks: Vec::new(),
+            nick_attempt: 0,
+            registered: false,
+            supports_monitor: false,
+            last_ison: None,
+            channels: channels,
+            ignores: Vec::new(),
+            owners: Vec::new(),
+            grants: Vec::new(),
+            greetings: default_greetings(),
+            commands: None,
+            api_keys: ApiKeys::default(),
+            caps: Capabilities::new(DEFAULT_CAPS.iter().map(|s| s.to_string()).collect()),
+            sasl: None,
+            sasl_failures: 0,
+            reload: None,
+            abort: None,
+            fatal: false,
             db: db,
-            last_greet: Utc::now(),
+            workers: WorkerPool::new(DEFAULT_WORKERS),
+            last_greet: HashMap::new(),
+            ctcp_limiter: TokenBucket::new(CTCP_BURST, CTCP_REFILL_PER_SEC),
+            limiter: RateLimiter::new(Default::default()),
+            http_budget: ratelimit::http_budget(DEFAULT_HTTP_PER_MINUTE),
+            lag: LagMonitor::new(
+                Timeout::from_secs(DEFAULT_PING_INTERVAL),
+                Timeout::from_secs(DEFAULT_PING_TIMEOUT),
+            ),
+            isupport: ISupport::new(),
+            members: Members::new(),
         };
     }
 
+    // loads this network's grants and ignores, the tables exist by now
     fn init(&mut self) -> Result<()> {
-        self.db.execute(CREATE_TABLE_SEEN_IDENTS, [])?;
-        self.db.execute(CREATE_TABLE_SEEN_URLS, [])?;
-        commands::nega::init(self)?;
+        permissions::init(self)?;
+        ignore::init(self)?;
         Ok(())
     }
 
-    fn set_ignore(&mut self, ignore: Option<Vec<String>>) {
-        self.ignore = ignore;
-    }
-
-    // FIXME move to commands/mod.rs
-    fn dispatch(
-        &mut self,
-        stream: &mut IrcConnection,
-        msg: &IrcMessage,
-        command: &String,
-        rest: &String,
-    ) -> Result<()> {
-        let handler: Option<Command> = match command.as_str() {
-            "weather" => Some(commands::weather::command),
-            "ud" => Some(commands::ud::command),
-            //"giphy" => Some(commands::giphy::command),
-            "strain" => Some(commands::strain::command),
-            "nega" => Some(commands::nega::command_nega),
-            "kudos" => Some(commands::nega::command_kudos),
-            //"image" => Some(commands::image::command),
-            _ => None,
+    fn http_budget(&self) -> MutexGuard<'_, TokenBucket> {
+        return self.http_budget.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn channel(&self, name: &str) -> Option<&ChannelConfig> {
+        return self.channels.iter().find(|c| self.isupport.casemapping.equals(&c.name, name));
+    }
+
+    // nick as seen_idents.nick_key stores it
+    fn nick_key(&self, nick: &str) -> String {
+        return self.isupport.casemapping.fold(nick);
+    }
+
+    // keys are folded with whatever casemapping the server last advertised,
+    // refold them when it advertises another
+    fn refold_nick_keys(&mut self) -> Result<usize> {
+        let casemapping = self.isupport.casemapping;
+        let tx = self.db.transaction()?;
+        let nicks = {
+            let mut stmt = tx.prepare("SELECT id, nick, nick_key FROM seen_idents WHERE network=?1")?;
+            let rows = stmt.query_map(params![self.network], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?))
+            })?;
+            rows.collect::<SQLResult<Vec<_>>>()?
         };
+        let mut refolded = 0;
+        for (id, nick, key) in nicks {
+            let folded = casemapping.fold(&nick);
+            if key.as_ref() != Some(&folded) {
+                tx.execute("UPDATE seen_idents SET nick_key=?1 WHERE id=?2", params![folded, id])?;
+                refolded += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(refolded)
+    }
+
+    fn is_owner(&self, msg: &IrcMessage) -> bool {
+        return self.owners.iter().any(|pattern| self.matches_mask(pattern, msg));
+    }
+
+    // owners from the config can't be locked out by a grant
+    fn level(&self, msg: &IrcMessage) -> Level {
+        if self.is_owner(msg) {
+            return Level::Owner;
+        }
+        let level = permissions::granted_level(self, msg).unwrap_or(Level::User);
+        if level == Level::Banned {
+            return level;
+        }
+        if level < Level::Op && self.members.is_op(&msg.args[0], &msg.prefix.nick) {
+            return Level::Op;
+        }
+        return level;
+    }
 
-        if let Some(handler_fn) = handler {
-            if let Err(e) = handler_fn(self, stream, &msg, rest) {
-                log::error!("command errored: {}", e);
+    // threads a reply under msg for clients that show it, when the server
+    // relays client tags and gave the message an id
+    fn reply_tags(&self, msg: &IrcMessage) -> HashMap<String, String> {
+        let mut tags = HashMap::new();
+        if let Some(msgid) = msg.tags.get("msgid") {
+            if self.has_cap("message-tags") {
+                tags.insert(String::from("+draft/reply"), msgid.clone());
             }
         }
+        return tags;
+    }
 
-        Ok(())
+    fn is_me(&self, nick: &str) -> bool {
+        return self.isupport.casemapping.equals(nick, &self.current_nick);
+    }
+
+    // nor by an ignore rule, so they can always take it back
+    fn is_ignored(&self, msg: &IrcMessage, scope: Scope) -> bool {
+        if self.is_owner(msg) {
+            return false;
+        }
+        return self.ignores.iter().any(|rule| rule.matches(self, msg, scope));
+    }
+
+    // $a:name matches a services account (needs account-tag), patterns with
+    // ! or @ are matched against nick!user@host, the rest against the nick
+    fn matches_mask(&self, pattern: &str, msg: &IrcMessage) -> bool {
+        let casemapping = self.isupport.casemapping;
+        let pattern = casemapping.fold(pattern);
+        if let Some(account) = pattern.strip_prefix("$a:") {
+            return match msg.tags.get("account") {
+                Some(name) => wildcard_match(account, &casemapping.fold(name)),
+                None => false,
+            };
+        }
+        if pattern.contains("!") || pattern.contains("@") {
+            let mask = format!("{}!{}@{}", msg.prefix.nick, msg.prefix.realname, msg.prefix.host);
+            return wildcard_match(&pattern, &casemapping.fold(&mask));
+        }
+        wildcard_match(&pattern, &casemapping.fold(&msg.prefix.nick))
+    }
+
+    fn set_greetings(&mut self, greetings: Vec<String>) {
+        self.greetings = greetings;
+    }
+
+    fn random_greeting(&self) -> String {
+        let mut rng = rand::thread_rng();
+        return self.greetings.iter().choose(&mut rng).cloned().unwrap_or_default();
+    }
+
+    // the network's command list narrows what any channel can enable
+    fn command_enabled(&self, channel: &str, command: &str) -> bool {
+        if let Some(commands) = &self.commands {
+            if !commands.iter().any(|c| c == command) {
+                return false;
+            }
+        }
+        match self.channel(channel) {
+            Some(channel) => channel.command_enabled(command),
+            None => true,
+        }
+    }
+
+    fn set_ping(&mut self, interval: Timeout, timeout: Timeout) {
+        self.lag = LagMonitor::new(interval, timeout);
+    }
+
+    fn set_alt_nicks(&mut self, alt_nicks: Vec<String>) {
+        self.alt_nicks = alt_nicks;
+    }
+
+    fn set_caps(&mut self, caps: Vec<String>) {
+        self.caps = Capabilities::new(caps);
+    }
+
+    fn has_cap(&self, cap: &str) -> bool {
+        return self.caps.is_enabled(cap);
+    }
+
+    fn set_sasl(&mut self, mechanism: Mechanism) {
+        self.caps.want("sasl");
+        self.sasl = Some(mechanism);
+    }
+
+    fn abort(&mut self, reason: Disconnect, fatal: bool) {
+        self.abort = Some(reason);
+        self.fatal = fatal;
     }
 
     fn get_ident(&mut self, msg: &IrcMessage) -> Option<Ident> {
         return self.db.query_row(
-            "SELECT id, host, nick, realname FROM seen_idents WHERE host=?1 AND nick=?2 AND realname=?3",
-            params![msg.prefix.host, msg.prefix.nick, msg.prefix.realname],
+            "SELECT id, host, nick, realname FROM seen_idents WHERE network=?1 AND host=?2 AND nick_key=?3 AND realname=?4",
+            params![self.network, msg.prefix.host, self.nick_key(&msg.prefix.nick), msg.prefix.realname],
             |row| {
                Ok(Ident {
                     id: row.get(0)?,
@@ -359,15 +890,15 @@ impl IrcBot {
             }).optional().unwrap()
     }
 
-    fn update_last_seen(&mut self, ident: &Ident) -> Result<()> {
-        self.db.execute("UPDATE seen_idents SET last_seen=?1 WHERE id=?2", params![Utc::now(), ident.id])?;
+    fn update_last_seen(&mut self, ident: &Ident, seen: DateTime<Utc>) -> Result<()> {
+        self.db.execute("UPDATE seen_idents SET last_seen=?1 WHERE id=?2", params![seen, ident.id])?;
         Ok(())
     }
 
     fn add_ident(&mut self, msg: &IrcMessage) -> Result<Ident> {
         self.db.execute(
-            "INSERT INTO seen_idents(host, nick, realname, last_seen) VALUES (?1, ?2, ?3, ?4)",
-            params![msg.prefix.host, msg.prefix.nick, msg.prefix.realname, Utc::now()],
+            "INSERT INTO seen_idents(network, host, nick, nick_key, realname, last_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![self.network, msg.prefix.host, msg.prefix.nick, self.nick_key(&msg.prefix.nick), msg.prefix.realname, msg.time()],
         )?;
 
         Ok(Ident {
@@ -380,12 +911,76 @@ impl IrcBot {
 
     fn ensure_ident(&mut self, msg: &IrcMessage) -> Result<Ident> {
         if let Some(ident) = self.get_ident(msg) {
-            self.update_last_seen(&ident)?;
+            self.update_last_seen(&ident, msg.time())?;
             return Ok(ident);
         }
         return self.add_ident(msg);
     }
 
+    // what they said last, clearing any event since speaking is newer
+    fn record_message(&mut self, ident: &Ident, msg: &IrcMessage, text: &str) -> Result<()> {
+        let text: String = text.chars().take(MAX_SEEN_MESSAGE_LEN).collect();
+        self.db.execute(
+            "UPDATE seen_idents SET last_event=NULL, last_event_channel=NULL, last_channel=?1, last_message=?2, last_spoke=?3
+             WHERE id=?4",
+            params![msg.args[0], text, msg.time(), ident.id],
+        )?;
+        Ok(())
+    }
+
+    // joins, parts and quits count as being seen too, channel is where it
+    // happened if it only concerns one
+    fn record_event(&mut self, msg: &IrcMessage, event: &str, channel: Option<&str>) -> Result<()> {
+        let ident = self.ensure_ident(msg)?;
+        self.db.execute(
+            "UPDATE seen_idents SET last_event=?1, last_event_channel=?2 WHERE id=?3",
+            params![event, channel, ident.id],
+        )?;
+        Ok(())
+    }
+
+    // carry the ident over to the new nick so lookups by nick stay current
+    fn rename_ident(&mut self, msg: &IrcMessage) -> Result<()> {
+        let new_nick = match msg.args.get(0) {
+            Some(nick) => nick.clone(),
+            None => return Ok(()),
+        };
+
+        let event = format!("changing nick from {}", msg.prefix.nick);
+        let existing: Option<i64> = self.db.query_row(
+            "SELECT id FROM seen_idents WHERE network=?1 AND host=?2 AND nick_key=?3 AND realname=?4",
+            params![self.network, msg.prefix.host, self.nick_key(&new_nick), msg.prefix.realname],
+            |row| row.get(0),
+        ).optional()?;
+
+        match existing {
+            // they've used this nick before, keep both rows and bump the one in use
+            Some(id) => {
+                self.db.execute(
+                    "UPDATE seen_idents SET last_seen=?1, last_event=?2, last_event_channel=NULL WHERE id=?3",
+                    params![msg.time(), event, id],
+                )?;
+            }
+            None => {
+                self.db.execute(
+                    "UPDATE seen_idents SET nick=?1, nick_key=?2, last_seen=?3, last_event=?4, last_event_channel=NULL
+                     WHERE network=?5 AND host=?6 AND nick_key=?7 AND realname=?8",
+                    params![
+                        new_nick,
+                        self.nick_key(&new_nick),
+                        msg.time(),
+                        event,
+                        self.network,
+                        msg.prefix.host,
+                        self.nick_key(&msg.prefix.nick),
+                        msg.prefix.realname
+                    ],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
     // FIXME refactor?
     fn find_ident_by_id(&self, ident_id: i64) -> Option<Ident> {
         return self.db.query_row(
@@ -404,8 +999,8 @@ impl IrcBot {
 
     fn find_ident_by_nick(&mut self, nick: &String) -> Option<Ident> {
         return self.db.query_row(
-            "SELECT id, host, nick, realname FROM seen_idents WHERE nick=?1 ORDER BY last_seen DESC",
-            params![nick],
+            "SELECT id, host, nick, realname FROM seen_idents WHERE network=?1 AND nick_key=?2 ORDER BY last_seen DESC",
+            params![self.network, self.nick_key(nick)],
             |row| {
                Ok(Ident {
                     id: row.get(0)?,
@@ -417,34 +1012,55 @@ impl IrcBot {
         ).optional().unwrap()
     }
 
-    fn see(&mut self, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
-        self.scrape_urls(stream, msg)?;
-        self.check_greeting(stream, msg)?;
-        self.check_emote(stream, msg)?;
+    fn on_action(&mut self, stream: &mut IrcConnection, msg: &IrcMessage, ctcp: &CtcpMessage) -> Result<()> {
+        if self.channel(&msg.args[0]).is_none() || self.is_me(&msg.prefix.nick) {
+            return Ok(());
+        }
+
+        let ident = self.ensure_ident(msg)?;
+        self.record_message(&ident, msg, &format!("* {} {}", msg.prefix.nick, ctcp.params))?;
+        self.see(stream, msg, &ctcp.params)?;
         Ok(())
     }
 
-    fn check_greeting(&mut self, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    fn see(&mut self, stream: &mut IrcConnection, msg: &IrcMessage, text: &str) -> Result<()> {
+        let channel = match self.channel(&msg.args[0]) {
+            Some(channel) => channel.clone(),
+            None => return Ok(()),
+        };
+
+        if channel.reposts && !self.is_ignored(msg, Scope::Urls) {
+            self.scrape_urls(stream, msg, text)?;
+        }
+        if channel.greetings && !self.is_ignored(msg, Scope::Greetings) {
+            self.check_greeting(stream, text, &channel)?;
+        }
+        self.check_emote(stream, msg, text)?;
+        Ok(())
+    }
+
+    fn check_greeting(&mut self, stream: &mut IrcConnection, text: &str, channel: &ChannelConfig) -> Result<()> {
 
         let now = Utc::now();
-        let should_greet = (self.last_greet + Duration::minutes(5)) < now;
-        if msg.args.len() == 2 && GREETINGS.contains(&msg.args[1].as_str()) && should_greet {
-            let target = &msg.args[0];
+        let should_greet = match self.last_greet.get(&channel.name) {
+            Some(last_greet) => (*last_greet + channel.greet_cooldown()) < now,
+            None => true,
+        };
+        if self.greetings.iter().any(|g| g == text) && should_greet {
             say(
                 stream,
-                &target,
-                &random_greeting(),
+                &channel.name,
+                &self.random_greeting(),
             )?;
-            self.last_greet = now;
+            self.last_greet.insert(channel.name.clone(), now);
         }
         Ok(())
     }
 
-    fn check_emote(&mut self, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    fn check_emote(&mut self, stream: &mut IrcConnection, msg: &IrcMessage, text: &str) -> Result<()> {
         let re = Regex::new(r"<3\b").unwrap();
         let target = &msg.args[0];
-        let text = msg.args[1..].join(" ");
-        if re.is_match(&text.as_str()) {
+        if re.is_match(text) {
             say(stream, &target, &String::from("❤️"))?;
         }
         Ok(())
@@ -455,10 +1071,15 @@ impl IrcBot {
         let mut context = Context::new(&SHA256);
         context.update(url.as_bytes());
         let url_hash = HEXLOWER.encode(context.finish().as_ref());
+        // the configured spelling, whatever case the server relayed
+        let channel = match self.channel(&msg.args[0]) {
+            Some(channel) => channel.name.clone(),
+            None => return Ok(()),
+        };
 
         let row = self.db.query_row(
-            "SELECT id, owner_id, url_hash, count, first_seen FROM seen_urls WHERE url_hash = ?1",
-            params![url_hash],
+            "SELECT id, owner_id, url_hash, count, first_seen FROM seen_urls WHERE network = ?1 AND channel = ?2 AND url_hash = ?3",
+            params![self.network, channel, url_hash],
             |row| {
                 Ok(SeenUrl {
                     id: row.get(0)?,
@@ -479,8 +1100,10 @@ impl IrcBot {
                 self.db.execute("UPDATE seen_urls SET count=count+1 WHERE id=?1", params![seen_url.id])?;
 
                 let owner = self.find_ident_by_id(seen_url.owner_id).unwrap();
-                say(
+                let tags = self.reply_tags(msg);
+                say_tagged(
                     stream,
+                    &tags,
                     &target,
                     &format!(
                         "repost: {} (first seen at {} by {} / repost count: {})",
@@ -493,20 +1116,19 @@ impl IrcBot {
             }
         } else {
             self.db.execute(
-                "INSERT INTO seen_urls (owner_id, url_hash, count, first_seen) VALUES (?1, ?2, ?3, ?4)",
-                params![ident.id, url_hash, 1, Utc::now()],
+                "INSERT INTO seen_urls (network, channel, owner_id, url_hash, count, first_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![self.network, channel, ident.id, url_hash, 1, msg.time()],
             )?;
         }
 
         Ok(())
     }
 
-    fn scrape_urls(&mut self, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    fn scrape_urls(&mut self, stream: &mut IrcConnection, msg: &IrcMessage, text: &str) -> Result<()> {
         let mut finder = LinkFinder::new();
         finder.url_must_have_scheme(false);
         finder.kinds(&[LinkKind::Url]);
-        let text = msg.args[1..].join(" ");
-        let links = finder.links(&text);
+        let links = finder.links(text);
         for link in links {
             let url = link.as_str();
             self.handle_url(stream, msg, url)?;
@@ -515,181 +1137,676 @@ impl IrcBot {
     }
 }
 
-fn handle_message(data: String, bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
-    let lines = data.lines();
-    for line in lines {
-        if line.len() == 0 {
-            break;
-        }
+// Creates and upgrades the tables in a database file. Runs once per file before
+// any network connects, so networks sharing one don't race each other.  Rows
+// from before the upgrade are credited to `first`, the first network using it.
+fn migrate(db_path: &str, first: &NetworkConfig) -> Result<()> {
+    let mut db = Connection::open(db_path)?;
+    db.busy_timeout(Timeout::from_secs(5))?;
+
+    migrate_seen_idents(&mut db)?;
+    migrate_seen_network(&mut db, first)?;
+    db.execute_batch(CREATE_TABLE_SEEN_IDENTS)?;
+    migrate_seen_activity(&mut db)?;
+    migrate_seen_event_channel(&mut db)?;
+    db.execute(CREATE_TABLE_SEEN_URLS, [])?;
+    migrate_seen_urls(&mut db, first)?;
+    commands::init(&db)?;
+    permissions::create_table(&db)?;
+    ignore::create_table(&db)?;
+    Ok(())
+}
 
-        let msg = parse_message(&mut String::from(line));
+fn migrate_seen_activity(db: &mut Connection) -> Result<()> {
+    if db.prepare("SELECT last_message FROM seen_idents LIMIT 1").is_ok() {
+        return Ok(());
+    }
 
-        if let Some(ignored) = &bot.ignore {
-            if ignored.iter().any(|s| s == &msg.prefix.nick) {
-                log::debug!("dropped: {:?}", msg);
-                return Ok(());
-            }
-        }
+    log::info!("migrating seen_idents, adding last message columns");
+    let tx = db.transaction()?;
+    tx.execute_batch(MIGRATE_SEEN_IDENTS_ACTIVITY)?;
+    tx.commit()?;
+    Ok(())
+}
 
-        log::debug!("incoming message: {:?}", msg);
+fn migrate_seen_event_channel(db: &mut Connection) -> Result<()> {
+    if db.prepare("SELECT last_event_channel FROM seen_idents LIMIT 1").is_ok() {
+        return Ok(());
+    }
 
-        let handler: Option<CallbackHandler> = match msg.command.as_str() {
-            "001" => Some(on_welcome),
-            "PRIVMSG" => Some(on_privmsg),
-            "PING" => Some(on_ping),
-            _ => None,
-        };
+    log::info!("migrating seen_idents, adding last_event_channel");
+    let tx = db.transaction()?;
+    tx.execute_batch(MIGRATE_SEEN_IDENTS_EVENT_CHANNEL)?;
+    tx.commit()?;
+    Ok(())
+}
 
-        if let Some(handler_fn) = handler {
-            if let Err(e) = handler_fn(bot, stream, &msg) {
-                log::error!("error handling message: {}", e);
-            }
-        }
+// nick_key holds the casefolded nick so lookups ignore case
+fn migrate_seen_idents(db: &mut Connection) -> Result<()> {
+    let exists = db.prepare("SELECT id FROM seen_idents LIMIT 1").is_ok();
+    if !exists || db.prepare("SELECT nick_key FROM seen_idents LIMIT 1").is_ok() {
+        return Ok(());
     }
 
+    log::info!("migrating seen_idents, adding nick_key");
+    let tx = db.transaction()?;
+    tx.execute("ALTER TABLE seen_idents ADD COLUMN nick_key TEXT", [])?;
+    let nicks = {
+        let mut stmt = tx.prepare("SELECT id, nick FROM seen_idents")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        rows.collect::<SQLResult<Vec<_>>>()?
+    };
+    // the server's casemapping isn't known yet, rfc1459 is the IRC default
+    for (id, nick) in nicks {
+        tx.execute(
+            "UPDATE seen_idents SET nick_key=?1 WHERE id=?2",
+            params![CaseMapping::Rfc1459.fold(&nick), id],
+        )?;
+    }
+    tx.commit()?;
     Ok(())
 }
 
-fn bot_main(running: &AtomicBool, bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
-    ident(stream, &bot.nick)?;
+fn migrate_seen_network(db: &mut Connection, first: &NetworkConfig) -> Result<()> {
+    let exists = db.prepare("SELECT id FROM seen_idents LIMIT 1").is_ok();
+    if !exists || db.prepare("SELECT network FROM seen_idents LIMIT 1").is_ok() {
+        return Ok(());
+    }
 
-    loop {
-        if !running.load(Ordering::Relaxed) {
-            break;
+    // idents seen before the upgrade belong to the first network
+    log::info!("migrating seen_idents, existing idents belong to {}", first.name());
+    let tx = db.transaction()?;
+    tx.execute_batch(MIGRATE_SEEN_IDENTS_NETWORK)?;
+    tx.execute("UPDATE seen_idents SET network=?1", params![first.name()])?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn migrate_seen_urls(db: &mut Connection, first: &NetworkConfig) -> Result<()> {
+    if db.prepare("SELECT channel FROM seen_urls LIMIT 1").is_err() {
+        // urls seen before the upgrade are credited to the first channel
+        let channel = first.channels.first().map(|c| c.name.clone()).unwrap_or_default();
+        log::info!("migrating seen_urls, existing urls belong to {}", channel);
+
+        let tx = db.transaction()?;
+        tx.execute_batch(MIGRATE_SEEN_URLS_CHANNEL)?;
+        tx.execute("UPDATE seen_urls SET channel=?1", params![channel])?;
+        tx.commit()?;
+    }
+
+    if db.prepare("SELECT network FROM seen_urls LIMIT 1").is_err() {
+        log::info!("migrating seen_urls, existing urls belong to {}", first.name());
+
+        let tx = db.transaction()?;
+        tx.execute_batch(MIGRATE_SEEN_URLS_NETWORK)?;
+        tx.execute("UPDATE seen_urls SET network=?1", params![first.name()])?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn handle_message(line: String, bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    let msg = match parse_message(&mut String::from(line.as_str())) {
+        Some(msg) => msg,
+        None => {
+            log::debug!("skipping line without a command: {:?}", line);
+            return Ok(());
         }
-        let mut buffer = [0; 4096];
-        match stream.read(&mut buffer) {
-                Ok(bytes) => {
-                    let s = String::from_utf8_lossy(&buffer[0..bytes]).to_string();
-                    if let Err(e) = handle_message(s, bot, stream) {
-                        log::error!("error handling message: {}", e);
-                    }
+    };
+    log::debug!("incoming message: {:?}", msg);
+
+    let handler: Option<CallbackHandler> = match msg.command.as_str() {
+        "001" => Some(on_welcome),
+        "PRIVMSG" => Some(on_privmsg),
+        "PING" => Some(on_ping),
+        "PONG" => Some(lag::on_pong),
+        "JOIN" => Some(on_join),
+        "NICK" => Some(on_nick),
+        "PART" => Some(on_part),
+        "KICK" => Some(members::on_kick),
+        "QUIT" => Some(on_quit),
+        "MODE" => Some(members::on_mode),
+        "353" => Some(members::on_names),
+        "366" => Some(members::on_end_of_names),
+        "005" => Some(isupport::on_isupport),
+        "303" => Some(nick::on_ison),
+        "433" | "436" | "437" => Some(nick::on_nick_unavailable),
+        "731" => Some(nick::on_monitor_offline),
+        "465" => Some(reconnect::on_banned),
+        "ERROR" => Some(reconnect::on_error),
+        "CAP" => Some(caps::on_cap),
+        "AUTHENTICATE" => Some(sasl::on_authenticate),
+        "900" | "902" | "903" | "904" | "905" | "906" | "907" | "908" => Some(sasl::on_numeric),
+        _ => None,
+    };
+
+    if let Some(handler_fn) = handler {
+        if let Err(e) = handler_fn(bot, stream, &msg) {
+            log::error!("error handling message: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn bot_main(
+    shutdown: &mut watch::Receiver<bool>,
+    bot: &mut IrcBot,
+    stream: &mut IrcConnection,
+    lines: &mut mpsc::Receiver<std::io::Result<String>>,
+    network: &mut NetworkConfig,
+    configs: &mut watch::Receiver<Arc<Reload>>,
+) -> Result<()> {
+    bot.abort = None;
+    nick::reset(bot);
+    bot.lag.reset();
+    bot.isupport = ISupport::new();
+    bot.members.reset();
+    bot.members.apply_isupport(&bot.isupport);
+    caps::begin(bot, stream)?;
+    ident(stream, &bot.nick, &bot.realname)?;
+
+    let mut ticker = interval(TICK);
+    while !*shutdown.borrow() {
+        tokio::select! {
+            Ok(()) = shutdown.changed() => {}
+            Ok(()) = configs.changed() => {
+                let reload = configs.borrow().clone();
+                if let Err(e) = reload::apply(bot, stream, network, &reload) {
+                    log::error!("error applying config: {}", e);
                 }
-                Err(e) => {
-                    if let ErrorKind::WouldBlock = e.kind() {
-                        continue;
+            }
+            _ = ticker.tick() => {
+                nick::tick(bot, stream)?;
+                lag::tick(bot, stream)?;
+            }
+            line = lines.recv() => match line {
+                Some(Ok(line)) => {
+                    bot.lag.saw_activity();
+                    if let Err(e) = handle_message(line, bot, stream) {
+                        log::error!("error handling message: {}", e);
                     }
-                    return Err(Box::new(e));
                 }
-            }
+                Some(Err(e)) => return Err(Box::new(e)),
+                None => return Err(Box::new(Error::new("connection closed"))),
+            },
+        }
+
+        if let Some(reason) = &bot.abort {
+            let reason = reason.to_string();
+            stream.flush_all().await?;
+            return Err(Box::new(Error::new(&reason)));
+        }
     }
 
     quit(stream, &String::from("out"))?;
+    stream.flush_all().await?;
 
     Ok(())
 }
 
-struct NoCertificateVerification {}
+async fn run_network(
+    mut network: NetworkConfig,
+    db_path: String,
+    api_keys: ApiKeys,
+    http_budget: HttpBudget,
+    mut shutdown: watch::Receiver<bool>,
+    mut configs: watch::Receiver<Arc<Reload>>,
+    reload: mpsc::UnboundedSender<ReloadRequest>,
+) -> Result<()> {
+    let db = Connection::open(&db_path)?;
+    // networks sharing a database take turns writing
+    db.busy_timeout(Timeout::from_secs(5))?;
+
+    let mut bot: IrcBot = IrcBot::new(network.host.clone(), network.nick.clone(), network.channels.clone(), db);
+    bot.network = network.name();
+    bot.init()?;
+    bot.reload = Some(reload);
+
+    ignore::set_config_rules(&mut bot, &network.ignore);
+
+    bot.owners = network.owners.clone();
+    bot.realname = network.realname();
+    bot.commands = network.commands.clone();
+    bot.api_keys = api_keys;
+    bot.http_budget = http_budget;
+    bot.limiter.set_cooldowns(network.cooldowns.clone());
+    if let Some(greetings) = &network.greetings {
+        bot.set_greetings(greetings.clone());
+    }
+
+    bot.set_alt_nicks(network.alt_nicks.clone());
+    bot.set_ping(
+        Timeout::from_secs(network.ping_interval.unwrap_or(DEFAULT_PING_INTERVAL)),
+        Timeout::from_secs(network.ping_timeout.unwrap_or(DEFAULT_PING_TIMEOUT)),
+    );
+
+    if let Some(caps) = &network.caps {
+        bot.set_caps(caps.clone());
+    }
 
-impl rustls::client::ServerCertVerifier for NoCertificateVerification {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &rustls::Certificate,
-        _intermediates: &[rustls::Certificate],
-        _server_name: &rustls::ServerName,
-        _scts: &mut dyn Iterator<Item = &[u8]>,
-        _ocsp: &[u8],
-        _now: std::time::SystemTime,
-    ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::ServerCertVerified::assertion())
+    if let Some(mechanism) = network.sasl_mechanism()? {
+        bot.set_sasl(mechanism);
     }
 
-    fn verify_tls12_signature(
-        &self,
-        message: &[u8],
-        cert: &rustls::Certificate,
-        dss: &rustls::internal::msgs::handshake::DigitallySignedStruct,
-    ) -> std::result::Result<rustls::client::HandshakeSignatureValid, rustls::Error> {
-        return Ok(HandshakeSignatureValid::assertion());
+    let config = if network.no_tls {
+        None
+    } else {
+        Some(Arc::new(tls::client_config(&network.tls)?))
+    };
+
+    let mut servers = vec![network.host.clone()];
+    servers.extend(network.servers.iter().cloned());
+    let mut reconnect = Reconnect::new(servers);
+
+    while !*shutdown.borrow() {
+        bot.host = reconnect.next_server();
+        log::info!("connecting to {}", bot.host);
+
+        let attempt = tokio::select! {
+            attempt = transport::connect(&bot.host, config.clone()) => attempt,
+            Ok(()) = shutdown.changed() => break,
+        };
+
+        let reason = match attempt {
+            Ok(transport) => {
+                let (read, write) = split(transport);
+                let (lines_tx, mut lines) = mpsc::channel(LINE_BACKLOG);
+                let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+                let reader = tokio::spawn(transport::reader(read, lines_tx));
+                let writer = tokio::spawn(transport::writer(write, outgoing_rx));
+
+                let mut stream = IrcConnection::new(
+                    outgoing,
+                    &bot.nick,
+                    network.max_lines.unwrap_or(DEFAULT_MAX_LINES),
+                );
+                let result = bot_main(
+                    &mut shutdown,
+                    &mut bot,
+                    &mut stream,
+                    &mut lines,
+                    &mut network,
+                    &mut configs,
+                )
+                .await;
+
+                if let Err(e) = stream.shutdown().await {
+                    log::debug!("error closing connection: {}", e);
+                }
+                reader.abort();
+                let _ = writer.await;
+
+                if bot.registered {
+                    reconnect.reset();
+                }
+
+                match (bot.abort.take(), result) {
+                    (Some(reason), _) => reason,
+                    (None, Err(e)) => Disconnect::Io(e.to_string()),
+                    (None, Ok(())) => break,
+                }
+            }
+            Err(e) => Disconnect::Connect(e.to_string()),
+        };
+
+        if bot.fatal {
+            log::error!("giving up on {}: {}", bot.host, reason);
+            break;
+        }
+
+        let delay = reconnect.delay(&reason);
+        log::warn!("{}: {}, reconnecting in {}s", bot.host, reason, delay.as_secs());
+        tokio::select! {
+            _ = sleep(delay) => {}
+            Ok(()) = shutdown.changed() => {}
+        }
     }
 
-    fn verify_tls13_signature(
-        &self,
-        message: &[u8],
-        cert: &rustls::Certificate,
-        dss: &rustls::internal::msgs::handshake::DigitallySignedStruct,
-    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
-        return Ok(HandshakeSignatureValid::assertion());
+    Ok(())
+}
+
+fn values(args: &ArgMatches, name: &str) -> Option<Vec<String>> {
+    return args.values_of(name).map(|v| v.map(String::from).collect());
+}
+
+// Flags given on the command line win over the config file, for every network
+// in it. Without a config they describe the only network.
+fn apply_overrides(network: &mut NetworkConfig, args: &ArgMatches) -> Result<()> {
+    if let Some(host) = args.value_of("host") {
+        network.host = String::from(host);
+    }
+    if let Some(nick) = args.value_of("nick") {
+        network.nick = String::from(nick);
+    }
+    if let Some(specs) = args.values_of("channel") {
+        let mut channels = Vec::new();
+        for spec in specs {
+            channels.push(ChannelConfig::parse(spec)?);
+        }
+        network.channels = channels;
+    }
+    if let Some(servers) = values(args, "server") {
+        network.servers = servers;
+    }
+    if let Some(alt_nicks) = values(args, "alt-nick") {
+        network.alt_nicks = alt_nicks;
+    }
+    if let Some(realname) = args.value_of("realname") {
+        network.realname = Some(String::from(realname));
+    }
+    if let Some(commands) = values(args, "command") {
+        network.commands = Some(commands);
+    }
+    if let Some(owners) = values(args, "owner") {
+        network.owners = owners;
+    }
+    if let Some(ignore) = values(args, "ignore") {
+        network.ignore = ignore;
+    }
+    if let Some(caps) = values(args, "cap") {
+        network.caps = Some(caps);
+    }
+    if let Some(sasl) = args.value_of("sasl") {
+        network.sasl = Some(String::from(sasl));
+    }
+    if let Some(path) = args.value_of("sasl-credentials") {
+        network.sasl_credentials = Some(String::from(path));
+    }
+    if args.is_present("no-tls") {
+        network.no_tls = true;
+    }
+    if let Some(value) = args.value_of("max-lines") {
+        network.max_lines = Some(value.parse()?);
+    }
+    if let Some(value) = args.value_of("ping-interval") {
+        network.ping_interval = Some(value.parse()?);
     }
+    if let Some(value) = args.value_of("ping-timeout") {
+        network.ping_timeout = Some(value.parse()?);
+    }
+    if args.is_present("insecure") {
+        network.tls.insecure = true;
+    }
+    if let Some(path) = args.value_of("ca-file") {
+        network.tls.ca_file = Some(String::from(path));
+    }
+    if let Some(pin) = args.value_of("pin-sha256") {
+        network.tls.pin_sha256 = Some(String::from(pin));
+    }
+    if let Some(path) = args.value_of("client-cert") {
+        network.tls.client_cert = Some(String::from(path));
+    }
+    if let Some(path) = args.value_of("client-key") {
+        network.tls.client_key = Some(String::from(path));
+    }
+    Ok(())
 }
 
+fn load_config(args: &ArgMatches) -> Result<Config> {
+    let mut config = match args.value_of("config") {
+        Some(path) => config::load(path)?,
+        None => {
+            let host = args.value_of("host").unwrap();
+            let nick = args.value_of("nick").unwrap();
+            Config {
+                database: None,
+                api_keys: ApiKeys::default(),
+                http_per_minute: None,
+                networks: vec![NetworkConfig::new(host, nick)],
+            }
+        }
+    };
+
+    if let Some(path) = args.value_of("database") {
+        config.database = Some(String::from(path));
+    }
+    for network in config.networks.iter_mut() {
+        apply_overrides(network, args)?;
+    }
+    config.validate()?;
+    Ok(config)
+}
 
-fn main() -> Result<()> {
+#[tokio::main]
+async fn main() -> Result<()> {
     env_logger::init();
 
     let args = App::new("rusty")
         .version("0.1")
-        .arg(Arg::new("host").takes_value(true).required(true).index(1))
-        .arg(Arg::new("nick").takes_value(true).required(true).index(2))
+        .arg(Arg::new("config").takes_value(true).long("config"))
+        .arg(Arg::new("host").takes_value(true).required_unless_present("config").index(1))
+        .arg(Arg::new("nick").takes_value(true).required_unless_present("config").index(2))
         .arg(
             Arg::new("channel")
                 .takes_value(true)
-                .required(true)
+                .required_unless_present("config")
+                .multiple(true)
                 .index(3),
         )
+        .arg(Arg::new("database").takes_value(true).long("database"))
+        .arg(Arg::new("realname").takes_value(true).long("realname"))
+        .arg(
+            Arg::new("command")
+                .takes_value(true)
+                .long("command")
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("owner")
+                .takes_value(true)
+                .long("owner")
+                .multiple_occurrences(true),
+        )
         .arg(
             Arg::new("ignore")
                 .takes_value(true)
                 .long("ignore")
                 .multiple_occurrences(true),
         )
+        .arg(
+            Arg::new("server")
+                .takes_value(true)
+                .long("server")
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("alt-nick")
+                .takes_value(true)
+                .long("alt-nick")
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("cap")
+                .takes_value(true)
+                .long("cap")
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("sasl")
+                .takes_value(true)
+                .long("sasl")
+                .possible_values(&["plain", "external"]),
+        )
+        .arg(Arg::new("sasl-credentials").takes_value(true).long("sasl-credentials"))
+        .arg(Arg::new("client-cert").takes_value(true).long("client-cert"))
+        .arg(Arg::new("client-key").takes_value(true).long("client-key"))
+        .arg(Arg::new("no-tls").long("no-tls"))
+        .arg(Arg::new("insecure").long("insecure"))
+        .arg(Arg::new("ca-file").takes_value(true).long("ca-file"))
+        .arg(Arg::new("pin-sha256").takes_value(true).long("pin-sha256"))
+        .arg(Arg::new("max-lines").takes_value(true).long("max-lines"))
+        .arg(Arg::new("ping-interval").takes_value(true).long("ping-interval"))
+        .arg(Arg::new("ping-timeout").takes_value(true).long("ping-timeout"))
         .get_matches();
 
-    let host = String::from(args.value_of("host").unwrap());
-    let nick = String::from(args.value_of("nick").unwrap());
-    let channel = String::from(args.value_of("channel").unwrap());
+    let config = match load_config(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    let api_keys = config.api_keys.clone().with_env();
+    let http_budget = ratelimit::http_budget(config.http_per_minute.unwrap_or(DEFAULT_HTTP_PER_MINUTE));
+
+    let (stop, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        match signal::ctrl_c().await {
+            Ok(()) => {
+                log::info!("shutdown..");
+                let _ = stop.send(true);
+            }
+            Err(e) => {
+                log::warn!("can't listen for ctrl-c: {}", e);
+                // dropping stop would wake every shutdown.changed() for good
+                std::future::pending::<()>().await;
+            }
+        }
+    });
+
+    let (configs, configs_rx) = watch::channel(Arc::new(Reload::new(config.clone())));
+    let (reload, requests) = mpsc::unbounded_channel();
+    tokio::spawn(reload::listen(args, configs, requests));
+
+    let mut migrated: Vec<String> = Vec::new();
+    for network in &config.networks {
+        let db_path = config.database.clone().unwrap_or(network.db_path());
+        if !migrated.contains(&db_path) {
+            migrate(&db_path, network)?;
+            migrated.push(db_path);
+        }
+    }
+
+    let mut handles = Vec::new();
+    for network in config.networks {
+        let name = network.name();
+        let db_path = config.database.clone().unwrap_or(network.db_path());
+        let api_keys = api_keys.clone();
+        let http_budget = http_budget.clone();
+        let shutdown = shutdown.clone();
+        let configs = configs_rx.clone();
+        let reload = reload.clone();
+        handles.push(tokio::spawn(async move {
+            if let Err(e) = run_network(network, db_path, api_keys, http_budget, shutdown, configs, reload).await {
+                log::error!("{} stopped: {}", name, e);
+            }
+        }));
+    }
+
+    for handle in handles {
+        if let Err(_) = handle.await {
+            log::error!("network task panicked");
+        }
+    }
 
-    let db_path = format!("./{nick}-at-{host}.db", host=host, nick=nick);
-    let db = Connection::open(&db_path).expect("cannot open db");
+    Ok(())
+}
 
-    let mut bot: IrcBot = IrcBot::new(host, nick, channel, db);
-    bot.init()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, message, parse};
 
-    if let Some(ignore) = args.values_of("ignore") {
-        let values: Vec<String> = ignore.map(|s| s.to_string()).collect();
-        bot.set_ignore(Some(values));
+    #[test]
+    fn unescapes_tag_values() {
+        assert_eq!(unescape_tag_value("a\\:b\\sc\\\\d\\re\\nf"), "a;b c\\d\re\nf");
+        assert_eq!(unescape_tag_value("plain"), "plain");
     }
 
-    let verifier = Arc::new(NoCertificateVerification {});
-    let config = ClientConfig::builder()
-        .with_safe_default_cipher_suites()
-        .with_safe_default_kx_groups()
-        .with_safe_default_protocol_versions()?
-        .with_custom_certificate_verifier(verifier)
-        .with_no_client_auth();
+    #[test]
+    fn drops_unknown_escapes_and_trailing_backslash() {
+        assert_eq!(unescape_tag_value("\\b"), "b");
+        assert_eq!(unescape_tag_value("end\\"), "end");
+        assert_eq!(unescape_tag_value("\\\\\\"), "\\");
+    }
 
-    static RUNNING: AtomicBool = AtomicBool::new(true);
-    ctrlc::set_handler(|| {
-        log::info!("shutdown..");
-        RUNNING.store(false, Ordering::Relaxed);
-    })?;
+    #[test]
+    fn escapes_tag_values() {
+        assert_eq!(escape_tag_value("a;b c\\d\re\nf"), "a\\:b\\sc\\\\d\\re\\nf");
+        assert_eq!(escape_tag_value("plain"), "plain");
+    }
 
-    while RUNNING.load(Ordering::Relaxed) {
-        log::info!("connecting to {}", bot.host);
+    #[test]
+    fn tags_round_trip() {
+        let mut tags = HashMap::new();
+        tags.insert(String::from("+draft/reply"), String::from("abc123"));
+        tags.insert(String::from("odd"), String::from("; \\\r\n\\s;;"));
+        tags.insert(String::from("flag"), String::new());
+        let formatted = format_tags(&tags);
+        assert_eq!(formatted, "@+draft/reply=abc123;flag;odd=\\:\\s\\\\\\r\\n\\\\s\\:\\:");
+        assert_eq!(parse_tags(&formatted[1..]), tags);
+    }
 
-        let mut host_parts = bot.host.split(":");
-        let server_name;
-        if let Some(host) = host_parts.next() {
-            server_name = host.try_into()?;
-        } else {
-            server_name = "".try_into()?;
-        }
+    #[test]
+    fn tags_survive_a_whole_line() {
+        let mut tags = HashMap::new();
+        tags.insert(String::from("+draft/reply"), String::from("a b;c"));
+        let line = format!("{} PRIVMSG #chan :hi there", format_tags(&tags));
+        let msg = message(&line);
+        assert_eq!(msg.tags, tags);
+        assert_eq!(msg.args, vec!["#chan", "hi there"]);
+    }
 
-        let mut conn = ClientConnection::new(Arc::new(config.clone()), server_name)?;
-        let mut tcp_stream = TcpStream::connect(&bot.host)?;
-        tcp_stream.set_read_timeout(Some(Timeout::from_millis(1000)))?;
-        let mut stream = Stream::new(&mut conn, &mut tcp_stream);
-        if let Err(e) = bot_main(&RUNNING, &mut bot, &mut stream) {
-            log::error!("bot errored: {}", e);
-        }
+    #[test]
+    fn parses_tags() {
+        let tags = parse_tags("time=2021-01-01T00:00:00.000Z;+draft/reply=abc;flag;;empty=;semi=a\\:b\\sc");
+        assert_eq!(tags["time"], "2021-01-01T00:00:00.000Z");
+        assert_eq!(tags["+draft/reply"], "abc");
+        assert_eq!(tags["flag"], "");
+        assert_eq!(tags["empty"], "");
+        assert_eq!(tags["semi"], "a;b c");
+        assert_eq!(tags.len(), 5);
+    }
 
-        stream.conn.send_close_notify();
-        stream.sock.shutdown(std::net::Shutdown::Both)?;
+    #[test]
+    fn parses_tagged_message() {
+        let msg = message("@msgid=x\\sy;account=alice :alice!al@host PRIVMSG #chan :hi there");
+        assert_eq!(msg.tags["msgid"], "x y");
+        assert_eq!(msg.tags["account"], "alice");
+        assert_eq!(msg.prefix.nick, "alice");
+        assert_eq!(msg.prefix.realname, "al");
+        assert_eq!(msg.prefix.host, "host");
+        assert_eq!(msg.command, "PRIVMSG");
+        assert_eq!(msg.args, vec!["#chan", "hi there"]);
+    }
 
-        if RUNNING.load(Ordering::Relaxed) {
-            thread::sleep(Timeout::from_millis(5000));
-        }
+    #[test]
+    fn parses_untagged_message() {
+        let msg = message("PING :server");
+        assert!(msg.tags.is_empty());
+        assert_eq!(msg.command, "PING");
+        assert_eq!(msg.args, vec!["server"]);
     }
 
-    Ok(())
+    #[test]
+    fn refolds_nick_keys_for_new_casemapping() {
+        let mut bot = bot();
+        bot.db.execute_batch(CREATE_TABLE_SEEN_IDENTS).unwrap();
+        bot.add_ident(&message(":Foo[m]!f@host PRIVMSG #chan :hi")).unwrap();
+        bot.add_ident(&message(":plain!p@host PRIVMSG #chan :hi")).unwrap();
+        bot.db
+            .execute("INSERT INTO seen_idents(network, nick, nick_key, last_seen) VALUES ('other', 'Bar[m]', 'bar{m}', ?1)", params![Utc::now()])
+            .unwrap();
+        assert!(bot.find_ident_by_nick(&String::from("foo{m}")).is_some());
+
+        bot.isupport.casemapping = CaseMapping::Ascii;
+        assert_eq!(bot.refold_nick_keys().unwrap(), 1);
+        assert!(bot.find_ident_by_nick(&String::from("foo{m}")).is_none());
+        assert!(bot.find_ident_by_nick(&String::from("FOO[M]")).is_some());
+        assert_eq!(bot.refold_nick_keys().unwrap(), 0);
+
+        // other networks sharing the database keep their own keys
+        let key: String = bot.db.query_row("SELECT nick_key FROM seen_idents WHERE network='other'", [], |row| row.get(0)).unwrap();
+        assert_eq!(key, "bar{m}");
+    }
+
+    #[test]
+    fn skips_lines_without_a_command() {
+        assert!(parse("").is_none());
+        assert!(parse("   ").is_none());
+        assert!(parse("@time=2021-01-01T00:00:00.000Z").is_none());
+        assert!(parse("@time=2021-01-01T00:00:00.000Z ").is_none());
+        assert!(parse(":server.example").is_none());
+    }
 }
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use crate::isupport::{CaseMapping, ISupport};
+use crate::{IrcBot, IrcConnection, IrcMessage, Result};
+
+#[derive(Debug, Clone)]
+struct Member {
+    nick: String,
+    // prefix symbols, e.g. "@+"
+    modes: String,
+}
+
+// Who is in each channel we're in, and which prefix modes they hold.
+// Channels and nicks are keyed by their casefolded form.
+#[derive(Debug)]
+pub struct Members {
+    casemapping: CaseMapping,
+    prefixes: Vec<(char, char)>,
+    channels: HashMap<String, HashMap<String, Member>>,
+    // NAMES replies collected until 366 RPL_ENDOFNAMES
+    pending: HashMap<String, HashMap<String, Member>>,
+}
+
+impl Members {
+    pub fn new() -> Members {
+        let isupport = ISupport::new();
+        return Members {
+            casemapping: isupport.casemapping,
+            prefixes: isupport.prefixes,
+            channels: HashMap::new(),
+            pending: HashMap::new(),
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.channels.clear();
+        self.pending.clear();
+    }
+
+    pub fn apply_isupport(&mut self, isupport: &ISupport) {
+        self.casemapping = isupport.casemapping;
+        self.prefixes = isupport.prefixes.clone();
+    }
+
+    fn key(&self, name: &str) -> String {
+        self.casemapping.fold(name)
+    }
+
+    pub fn is_present(&self, channel: &str, nick: &str) -> bool {
+        match self.channels.get(&self.key(channel)) {
+            Some(members) => members.contains_key(&self.key(nick)),
+            None => false,
+        }
+    }
+
+    // prefix symbols held by nick in channel, "" for a plain member
+    pub fn modes(&self, channel: &str, nick: &str) -> Option<&String> {
+        let member = self.channels.get(&self.key(channel))?.get(&self.key(nick))?;
+        Some(&member.modes)
+    }
+
+    // @ or anything ranked above it in PREFIX
+    pub fn is_op(&self, channel: &str, nick: &str) -> bool {
+        let op = match self.prefixes.iter().position(|(mode, _)| *mode == 'o') {
+            Some(op) => op,
+            None => return false,
+        };
+        match self.modes(channel, nick) {
+            Some(modes) => self.prefixes[..=op].iter().any(|(_, symbol)| modes.contains(*symbol)),
+            None => false,
+        }
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        self.prefixes.iter().any(|(_, symbol)| *symbol == c)
+    }
+
+    fn symbol_for(&self, mode: char) -> Option<char> {
+        self.prefixes.iter().find(|(m, _)| *m == mode).map(|(_, symbol)| *symbol)
+    }
+
+    // keep symbols in PREFIX order so the first one is always the highest
+    fn sorted(&self, symbols: &str) -> String {
+        self.prefixes
+            .iter()
+            .filter(|(_, symbol)| symbols.contains(*symbol))
+            .map(|(_, symbol)| *symbol)
+            .collect()
+    }
+
+    // "@+nick" or "@nick!user@host" with userhost-in-names
+    fn split_name<'a>(&self, name: &'a str) -> (String, &'a str) {
+        let start = name.find(|c| !self.is_symbol(c)).unwrap_or(name.len());
+        let nick = &name[start..];
+        let nick = match nick.find("!") {
+            Some(end) => &nick[..end],
+            None => nick,
+        };
+        (String::from(&name[..start]), nick)
+    }
+
+    fn member(&self, nick: &str, modes: &str) -> Member {
+        Member {
+            nick: String::from(nick),
+            modes: self.sorted(modes),
+        }
+    }
+
+    fn add(&mut self, channel: &str, nick: &str) {
+        let (channel, key) = (self.key(channel), self.key(nick));
+        let member = self.member(nick, "");
+        if let Some(members) = self.channels.get_mut(&channel) {
+            members.entry(key).or_insert(member);
+        }
+    }
+
+    fn remove(&mut self, channel: &str, nick: &str) {
+        let (channel, key) = (self.key(channel), self.key(nick));
+        if let Some(members) = self.channels.get_mut(&channel) {
+            members.remove(&key);
+        }
+    }
+
+    fn set_mode(&mut self, channel: &str, nick: &str, mode: char, enable: bool) {
+        let symbol = match self.symbol_for(mode) {
+            Some(symbol) => symbol,
+            None => return,
+        };
+        let current = match self.modes(channel, nick) {
+            Some(modes) => modes.clone(),
+            None => return,
+        };
+
+        let updated = if enable {
+            self.sorted(&format!("{}{}", current, symbol))
+        } else {
+            current.replace(symbol, "")
+        };
+        let (channel, key) = (self.key(channel), self.key(nick));
+        if let Some(member) = self.channels.get_mut(&channel).and_then(|m| m.get_mut(&key)) {
+            member.modes = updated;
+        }
+    }
+
+    fn rename(&mut self, old: &str, new: &str) {
+        let (old, key) = (self.key(old), self.key(new));
+        for members in self.channels.values_mut() {
+            if let Some(mut member) = members.remove(&old) {
+                member.nick = String::from(new);
+                members.insert(key.clone(), member);
+            }
+        }
+    }
+
+    fn quit(&mut self, nick: &str) {
+        let key = self.key(nick);
+        for members in self.channels.values_mut() {
+            members.remove(&key);
+        }
+    }
+
+    fn joined(&mut self, channel: &str) {
+        self.channels.insert(self.key(channel), HashMap::new());
+    }
+
+    fn parted(&mut self, channel: &str) {
+        self.channels.remove(&self.key(channel));
+    }
+}
+
+// 353 RPL_NAMREPLY: <me> <=|*|@> <channel> :<names>
+pub fn on_names(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if msg.args.len() < 4 {
+        return Ok(());
+    }
+
+    let channel = bot.members.key(&msg.args[2]);
+    let mut names = HashMap::new();
+    for name in msg.args[3].split_whitespace() {
+        let (symbols, nick) = bot.members.split_name(name);
+        names.insert(bot.members.key(nick), bot.members.member(nick, &symbols));
+    }
+    bot.members.pending.entry(channel).or_default().extend(names);
+    Ok(())
+}
+
+// 366 RPL_ENDOFNAMES: <me> <channel> :End of /NAMES list
+pub fn on_end_of_names(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let channel = match msg.args.get(1) {
+        Some(channel) => bot.members.key(channel),
+        None => return Ok(()),
+    };
+
+    let names = bot.members.pending.remove(&channel).unwrap_or_default();
+    // a NAMES for a channel we're not in doesn't tell us who's with us
+    if let Some(members) = bot.members.channels.get_mut(&channel) {
+        log::debug!("{} has {} members", channel, names.len());
+        *members = names;
+    }
+    Ok(())
+}
+
+pub fn on_join(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let channel = match msg.args.get(0) {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+
+    if bot.is_me(&msg.prefix.nick) {
+        // the NAMES reply that follows our own JOIN fills it in
+        bot.members.joined(channel);
+    }
+    bot.members.add(channel, &msg.prefix.nick);
+    Ok(())
+}
+
+fn left(bot: &mut IrcBot, channel: &str, nick: &str) {
+    if bot.is_me(nick) {
+        bot.members.parted(channel);
+    } else {
+        bot.members.remove(channel, nick);
+    }
+}
+
+pub fn on_part(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if let Some(channel) = msg.args.get(0) {
+        left(bot, channel, &msg.prefix.nick);
+    }
+    Ok(())
+}
+
+// KICK <channel> <nick> [:reason]
+pub fn on_kick(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if msg.args.len() < 2 {
+        return Ok(());
+    }
+    left(bot, &msg.args[0], &msg.args[1]);
+    Ok(())
+}
+
+pub fn on_quit(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    bot.members.quit(&msg.prefix.nick);
+    Ok(())
+}
+
+// called after nick::on_nick, which may already have renamed us
+pub fn on_nick(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if let Some(new) = msg.args.get(0) {
+        bot.members.rename(&msg.prefix.nick, new);
+    }
+    Ok(())
+}
+
+// MODE <channel> <modes> [params...], only prefix modes matter here
+pub fn on_mode(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if msg.args.len() < 2 || !bot.members.channels.contains_key(&bot.members.key(&msg.args[0])) {
+        return Ok(());
+    }
+
+    let channel = msg.args[0].clone();
+    let mut params = msg.args[2..].iter();
+    let mut enable = true;
+    for mode in msg.args[1].chars() {
+        match mode {
+            '+' => enable = true,
+            '-' => enable = false,
+            _ if bot.members.symbol_for(mode).is_some() => {
+                if let Some(nick) = params.next() {
+                    bot.members.set_mode(&channel, nick, mode, enable);
+                }
+            }
+            _ if bot.isupport.takes_param(mode, enable) => {
+                params.next();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, connection, message};
+
+    // rusty in #rust with an op, a voice and a plain member
+    fn joined() -> IrcBot {
+        let mut bot = bot();
+        let (mut stream, _lines) = connection();
+        on_join(&mut bot, &mut stream, &message(":rusty!r@example.org JOIN #rust")).unwrap();
+        on_names(&mut bot, &mut stream, &message(":server 353 rusty = #rust :@alice +bob carol rusty")).unwrap();
+        on_end_of_names(&mut bot, &mut stream, &message(":server 366 rusty #rust :End of /NAMES list")).unwrap();
+        return bot;
+    }
+
+    fn mode(bot: &mut IrcBot, line: &str) {
+        let (mut stream, _lines) = connection();
+        on_mode(bot, &mut stream, &message(line)).unwrap();
+    }
+
+    #[test]
+    fn splits_prefixes_from_names() {
+        let members = Members::new();
+        assert_eq!(members.split_name("@+alice"), (String::from("@+"), "alice"));
+        assert_eq!(members.split_name("bob"), (String::new(), "bob"));
+        assert_eq!(members.split_name("+carol!c@example.org"), (String::from("+"), "carol"));
+        assert_eq!(members.split_name("@"), (String::from("@"), ""));
+    }
+
+    #[test]
+    fn list_modes_take_a_parameter() {
+        let mut bot = joined();
+        mode(&mut bot, ":alice!a@example.org MODE #rust +bov *!*@spam.example carol bob");
+        assert!(bot.members.is_op("#rust", "carol"));
+        assert_eq!(bot.members.modes("#rust", "bob"), Some(&String::from("+")));
+        assert_eq!(bot.members.modes("#rust", "alice"), Some(&String::from("@")));
+    }
+
+    #[test]
+    fn keys_always_take_a_parameter_limits_only_when_set() {
+        let mut bot = joined();
+        mode(&mut bot, ":alice!a@example.org MODE #rust +kl-o secret 10 alice");
+        assert!(!bot.members.is_op("#rust", "alice"));
+
+        mode(&mut bot, ":alice!a@example.org MODE #rust -k-l+o secret bob");
+        assert_eq!(bot.members.modes("#rust", "bob"), Some(&String::from("@+")));
+
+        mode(&mut bot, ":bob!b@example.org MODE #rust -lv bob");
+        assert_eq!(bot.members.modes("#rust", "bob"), Some(&String::from("@")));
+    }
+
+    #[test]
+    fn ignores_modes_for_other_channels_and_users() {
+        let mut bot = joined();
+        mode(&mut bot, ":alice!a@example.org MODE #other +o carol");
+        mode(&mut bot, ":rusty MODE rusty +i");
+        assert_eq!(bot.members.modes("#rust", "carol"), Some(&String::new()));
+    }
+
+    #[test]
+    fn renames_keep_modes() {
+        let mut bot = joined();
+        bot.members.rename("ALICE", "Alice2");
+        assert!(!bot.members.is_present("#rust", "alice"));
+        assert!(bot.members.is_op("#rust", "alice2"));
+
+        // a change of case only
+        bot.members.rename("bob", "Bob");
+        assert!(bot.members.is_present("#rust", "BOB"));
+        assert_eq!(bot.members.modes("#rust", "bob"), Some(&String::from("+")));
+    }
+}
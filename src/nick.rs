@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+use crate::{send, IrcBot, IrcConnection, IrcMessage, Result};
+
+// how often to ISON for our nick when the server has no MONITOR
+const ISON_INTERVAL: Duration = Duration::from_secs(60);
+
+fn change_nick(stream: &mut IrcConnection, nick: &String) -> Result<()> {
+    send(stream, &format!("NICK {}", nick))
+}
+
+pub fn reset(bot: &mut IrcBot) {
+    bot.current_nick = bot.nick.clone();
+    bot.nick_attempt = 0;
+    bot.registered = false;
+    bot.supports_monitor = false;
+    bot.last_ison = None;
+}
+
+// 433 ERR_NICKNAMEINUSE, 436 ERR_NICKCOLLISION, 437 ERR_UNAVAILRESOURCE
+pub fn on_nick_unavailable(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if bot.registered {
+        // a regain attempt lost the race, keep the nick we have
+        log::debug!("{} is still unavailable", bot.nick);
+        return Ok(());
+    }
+
+    let rejected = msg.args.get(1).cloned().unwrap_or(bot.current_nick.clone());
+    let next = match bot.alt_nicks.get(bot.nick_attempt) {
+        Some(alt) => alt.clone(),
+        None => {
+            // a nick already at NICKLEN would come back truncated to the same one
+            let mut base = rejected.clone();
+            if let Some(nicklen) = bot.isupport.nicklen {
+                while base.chars().count() >= nicklen && base.pop().is_some() {}
+            }
+            format!("{}_", base)
+        }
+    };
+    bot.nick_attempt += 1;
+
+    log::warn!("nick {} unavailable, trying {}", rejected, next);
+    bot.current_nick = next.clone();
+    stream.nick = next.clone();
+    change_nick(stream, &next)
+}
+
+// called from 001 once the server has told us which nick we ended up with
+pub fn on_registered(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    bot.registered = true;
+    if let Some(nick) = msg.args.get(0) {
+        bot.current_nick = nick.clone();
+        stream.nick = nick.clone();
+    }
+    Ok(())
+}
+
+pub fn on_nick(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if !bot.is_me(&msg.prefix.nick) || msg.args.len() < 1 {
+        return Ok(());
+    }
+
+    bot.current_nick = msg.args[0].clone();
+    stream.nick = bot.current_nick.clone();
+    stream.self_prefix = None;
+    log::info!("nick is now {}", bot.current_nick);
+
+    if bot.is_me(&bot.nick) && bot.supports_monitor {
+        send(stream, &format!("MONITOR - {}", bot.nick))?;
+    }
+    Ok(())
+}
+
+// called from isupport::on_isupport, start watching our nick if it was taken
+pub fn on_isupport(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let monitor = msg.args.iter().any(|token| token == "MONITOR" || token.starts_with("MONITOR="));
+    if monitor && !bot.supports_monitor {
+        bot.supports_monitor = true;
+        if !bot.is_me(&bot.nick) {
+            send(stream, &format!("MONITOR + {}", bot.nick))?;
+        }
+    }
+    Ok(())
+}
+
+// 731 RPL_MONOFFLINE
+pub fn on_monitor_offline(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let offline = msg.args.last().cloned().unwrap_or_default();
+    let wanted = |n: &str| bot.isupport.casemapping.equals(n, &bot.nick);
+    if !bot.is_me(&bot.nick) && offline.split(",").any(wanted) {
+        change_nick(stream, &bot.nick)?;
+    }
+    Ok(())
+}
+
+// 303 RPL_ISON
+pub fn on_ison(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let online = msg.args.last().cloned().unwrap_or_default();
+    let wanted = |n: &str| bot.isupport.casemapping.equals(n, &bot.nick);
+    if !bot.is_me(&bot.nick) && !online.split_whitespace().any(wanted) {
+        change_nick(stream, &bot.nick)?;
+    }
+    Ok(())
+}
+
+pub fn tick(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    if !bot.registered || bot.supports_monitor || bot.is_me(&bot.nick) {
+        return Ok(());
+    }
+
+    let due = match bot.last_ison {
+        Some(last) => last.elapsed() >= ISON_INTERVAL,
+        None => true,
+    };
+    if due {
+        bot.last_ison = Some(Instant::now());
+        send(stream, &format!("ISON {}", bot.nick))?;
+    }
+    Ok(())
+}
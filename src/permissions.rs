@@ -0,0 +1,288 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, Result as SQLResult};
+
+use crate::commands::Level;
+use crate::utils::wildcard_overlap;
+use crate::{IrcBot, IrcMessage, Result};
+
+static CREATE_TABLE_PERMISSIONS: &str = "
+CREATE TABLE IF NOT EXISTS permissions (
+    id INTEGER PRIMARY KEY,
+    network TEXT NOT NULL,
+    mask TEXT NOT NULL,
+    level TEXT NOT NULL,
+    granted_by TEXT NOT NULL,
+    granted_at DATETIME NOT NULL,
+    UNIQUE (network, mask)
+);
+";
+
+// A level granted at runtime to everyone matching mask, either a
+// nick!user@host glob or $a:account for a services account.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub mask: String,
+    pub level: Level,
+}
+
+// $a:account or nick!user@host with all three parts, so nobody gets a level
+// just by taking a nick
+pub fn is_full_mask(mask: &str) -> bool {
+    if let Some(account) = mask.strip_prefix("$a:") {
+        return account.len() > 0;
+    }
+    let mut parts = mask.splitn(2, "!");
+    let nick = parts.next().unwrap_or("");
+    let mut userhost = parts.next().unwrap_or("").splitn(2, "@");
+    let user = userhost.next().unwrap_or("");
+    let host = userhost.next().unwrap_or("");
+    return nick.len() > 0 && user.len() > 0 && host.len() > 0 && !host.contains("@") && !mask.contains(" ");
+}
+
+pub fn create_table(db: &Connection) -> Result<()> {
+    db.execute(CREATE_TABLE_PERMISSIONS, [])?;
+    Ok(())
+}
+
+// loads this network's grants, the database may be shared with others
+pub fn init(bot: &mut IrcBot) -> Result<()> {
+    let rows = {
+        let db = &bot.db;
+        let mut stmt = db.prepare("SELECT mask, level FROM permissions WHERE network=?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![bot.network], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        rows.collect::<SQLResult<Vec<_>>>()?
+    };
+
+    bot.grants.clear();
+    for (mask, level) in rows {
+        if !is_full_mask(&mask) {
+            log::warn!("ignoring grant of {} to {}, it isn't nick!user@host or $a:account", level, mask);
+            continue;
+        }
+        match Level::from_name(&level) {
+            Some(level) => bot.grants.push(Grant { mask: mask, level: level }),
+            None => log::warn!("ignoring grant of unknown level {} to {}", level, mask),
+        }
+    }
+    Ok(())
+}
+
+// the highest level granted to whoever sent msg, so a broad ban can't lock
+// out someone granted more
+pub fn granted_level(bot: &IrcBot, msg: &IrcMessage) -> Option<Level> {
+    let mut granted = None;
+    for grant in &bot.grants {
+        if !bot.matches_mask(&grant.mask, msg) {
+            continue;
+        }
+        if granted.map_or(true, |level| grant.level > level) {
+            granted = Some(grant.level);
+        }
+    }
+    granted
+}
+
+// whether someone could match both masks; an account and a hostmask might
+// belong to the same person but there's no telling, so they don't overlap
+pub fn masks_overlap(bot: &IrcBot, a: &str, b: &str) -> bool {
+    let casemapping = bot.isupport.casemapping;
+    let (a, b) = (casemapping.fold(a), casemapping.fold(b));
+    match (a.strip_prefix("$a:"), b.strip_prefix("$a:")) {
+        (Some(a), Some(b)) => wildcard_overlap(a, b),
+        (None, None) => wildcard_overlap(&a, &b),
+        _ => false,
+    }
+}
+
+// owner masks and masks granted level or above
+pub fn protected_masks(bot: &IrcBot, level: Level) -> Vec<String> {
+    let grants = bot.grants.iter().filter(|g| g.level >= level).map(|g| &g.mask);
+    return bot.owners.iter().chain(grants).cloned().collect();
+}
+
+// an owner or grant at or above level that mask would also match, if any
+pub fn protected_by(bot: &IrcBot, mask: &str, level: Level) -> Option<String> {
+    return protected_masks(bot, level).into_iter().find(|protected| masks_overlap(bot, mask, protected));
+}
+
+// a grant to another mask that outranks level for someone matching both,
+// since the highest grant wins
+pub fn outranked_by(bot: &IrcBot, mask: &str, level: Level) -> Option<Grant> {
+    let casemapping = bot.isupport.casemapping;
+    return bot
+        .grants
+        .iter()
+        .find(|g| g.level > level && !casemapping.equals(&g.mask, mask) && masks_overlap(bot, mask, &g.mask))
+        .cloned();
+}
+
+pub fn find(bot: &IrcBot, mask: &str) -> Option<Grant> {
+    let casemapping = bot.isupport.casemapping;
+    return bot.grants.iter().find(|g| casemapping.equals(&g.mask, mask)).cloned();
+}
+
+pub fn grant(bot: &mut IrcBot, mask: &str, level: Level, granted_by: &str) -> Result<()> {
+    revoke(bot, mask)?;
+    bot.db.execute(
+        "INSERT INTO permissions (network, mask, level, granted_by, granted_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![bot.network, mask, level.to_string(), granted_by, Utc::now()],
+    )?;
+    bot.grants.push(Grant {
+        mask: String::from(mask),
+        level: level,
+    });
+    Ok(())
+}
+
+// false if nothing was granted to mask
+pub fn revoke(bot: &mut IrcBot, mask: &str) -> Result<bool> {
+    let existing = match find(bot, mask) {
+        Some(grant) => grant,
+        None => return Ok(false),
+    };
+    bot.db.execute(
+        "DELETE FROM permissions WHERE network=?1 AND mask=?2",
+        params![bot.network, existing.mask],
+    )?;
+    bot.grants.retain(|g| g.mask != existing.mask);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, message};
+
+    fn bot_with(grants: &[(&str, Level)]) -> IrcBot {
+        let mut bot = bot();
+        for (mask, level) in grants {
+            bot.grants.push(Grant {
+                mask: String::from(*mask),
+                level: *level,
+            });
+        }
+        return bot;
+    }
+
+    #[test]
+    fn full_masks() {
+        assert!(is_full_mask("alice!al@example.org"));
+        assert!(is_full_mask("*!*@*"));
+        assert!(is_full_mask("$a:alice"));
+        assert!(is_full_mask("$a:*"));
+        assert!(!is_full_mask("alice"));
+        assert!(!is_full_mask("al*"));
+        assert!(!is_full_mask("alice!al"));
+        assert!(!is_full_mask("al@example.org"));
+        assert!(!is_full_mask("!al@example.org"));
+        assert!(!is_full_mask("alice!@example.org"));
+        assert!(!is_full_mask("alice!al@"));
+        assert!(!is_full_mask("alice!al@host@host"));
+        assert!(!is_full_mask("$a:"));
+        assert!(!is_full_mask(""));
+    }
+
+    #[test]
+    fn matches_accounts() {
+        let bot = bot_with(&[]);
+        let msg = message("@account=Alice :alice!al@example.org PRIVMSG #rust :hi");
+        assert!(bot.matches_mask("$a:alice", &msg));
+        assert!(bot.matches_mask("$a:al*", &msg));
+        assert!(!bot.matches_mask("$a:bob", &msg));
+
+        let anonymous = message(":alice!al@example.org PRIVMSG #rust :hi");
+        assert!(!bot.matches_mask("$a:alice", &anonymous));
+        assert!(!bot.matches_mask("$a:*", &anonymous));
+    }
+
+    #[test]
+    fn matches_hostmasks_ignoring_case() {
+        let bot = bot_with(&[]);
+        let msg = message(":Alice[m]!al@Example.org PRIVMSG #rust :hi");
+        assert!(bot.matches_mask("alice{m}!al@example.org", &msg));
+        assert!(bot.matches_mask("*!*@*.org", &msg));
+        assert!(bot.matches_mask("*@example.org", &msg));
+        assert!(!bot.matches_mask("*!*@example.com", &msg));
+        assert!(!bot.matches_mask("bob!al@example.org", &msg));
+    }
+
+    #[test]
+    fn matches_bare_nicks() {
+        let bot = bot_with(&[]);
+        let msg = message(":Alice!al@example.org PRIVMSG #rust :hi");
+        assert!(bot.matches_mask("alice", &msg));
+        assert!(bot.matches_mask("ali?e", &msg));
+        assert!(!bot.matches_mask("al", &msg));
+        // a bare nick never looks at the host
+        assert!(!bot.matches_mask("example.org", &msg));
+    }
+
+    #[test]
+    fn highest_grant_wins() {
+        let bot = bot_with(&[
+            ("*!*@*", Level::Banned),
+            ("alice!*@example.org", Level::Admin),
+            ("$a:alice", Level::Trusted),
+        ]);
+        let alice = message("@account=alice :alice!al@example.org PRIVMSG #rust :hi");
+        assert_eq!(granted_level(&bot, &alice), Some(Level::Admin));
+        let elsewhere = message("@account=alice :alice!al@example.com PRIVMSG #rust :hi");
+        assert_eq!(granted_level(&bot, &elsewhere), Some(Level::Trusted));
+        let bob = message(":bob!bob@example.org PRIVMSG #rust :hi");
+        assert_eq!(granted_level(&bot, &bob), Some(Level::Banned));
+    }
+
+    #[test]
+    fn nothing_granted() {
+        let bot = bot_with(&[("alice!*@*", Level::Admin)]);
+        let bob = message(":bob!bob@example.org PRIVMSG #rust :hi");
+        assert_eq!(granted_level(&bot, &bob), None);
+        assert_eq!(bot.level(&bob), Level::User);
+    }
+
+    #[test]
+    fn config_owners_outrank_bans() {
+        let mut bot = bot_with(&[("*!*@*", Level::Banned)]);
+        bot.owners.push(String::from("boss!*@example.org"));
+        let boss = message(":boss!b@example.org PRIVMSG #rust :hi");
+        assert_eq!(bot.level(&boss), Level::Owner);
+        let bob = message(":bob!bob@example.org PRIVMSG #rust :hi");
+        assert_eq!(bot.level(&bob), Level::Banned);
+    }
+
+    #[test]
+    fn overlapping_masks() {
+        let bot = bot_with(&[]);
+        assert!(masks_overlap(&bot, "*!*@*", "boss!b@example.org"));
+        assert!(masks_overlap(&bot, "boss!b@example.org", "*!*@*"));
+        assert!(masks_overlap(&bot, "a*!*@*", "*b!*@*"));
+        assert!(masks_overlap(&bot, "BOSS!*@*", "boss!b@example.org"));
+        assert!(!masks_overlap(&bot, "*!*@example.com", "boss!*@example.org"));
+        assert!(masks_overlap(&bot, "$a:*", "$a:boss"));
+        assert!(!masks_overlap(&bot, "$a:bob", "$a:boss"));
+        assert!(!masks_overlap(&bot, "$a:*", "*!*@*"));
+    }
+
+    #[test]
+    fn protects_higher_levels() {
+        let mut bot = bot_with(&[("admin!*@example.org", Level::Admin), ("$a:helper", Level::Trusted)]);
+        bot.owners.push(String::from("$a:boss"));
+        assert_eq!(protected_by(&bot, "*!*@*", Level::Admin), Some(String::from("admin!*@example.org")));
+        assert_eq!(protected_by(&bot, "$a:*", Level::Admin), Some(String::from("$a:boss")));
+        assert_eq!(protected_by(&bot, "$a:helper", Level::Admin), None);
+        assert_eq!(protected_by(&bot, "*!*@example.com", Level::Admin), None);
+        assert_eq!(protected_by(&bot, "*!*@*", Level::Owner), None);
+    }
+
+    #[test]
+    fn finds_outranking_grants() {
+        let bot = bot_with(&[("*!*@corp.example", Level::Trusted), ("$a:helper", Level::Admin)]);
+        let outranked = outranked_by(&bot, "troll!*@corp.example", Level::Banned).unwrap();
+        assert_eq!(outranked.mask, "*!*@corp.example");
+        assert!(outranked_by(&bot, "troll!*@corp.example", Level::Trusted).is_none());
+        // the grant being replaced doesn't count
+        assert!(outranked_by(&bot, "*!*@CORP.example", Level::Banned).is_none());
+        assert!(outranked_by(&bot, "*!*@other.example", Level::Banned).is_none());
+        assert_eq!(outranked_by(&bot, "$a:*", Level::Trusted).unwrap().mask, "$a:helper");
+    }
+}
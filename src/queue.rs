@@ -0,0 +1,281 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Flood protection modelled on the ircd penalty scheme: every line costs a
+// token plus a little more for long lines, tokens refill at a fixed rate and
+// a full bucket allows a short burst.
+const BURST: f64 = 10.0;
+const REFILL_PER_SEC: f64 = 0.5;
+const BYTES_PER_TOKEN: f64 = 256.0;
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, rate: f64) -> TokenBucket {
+        return TokenBucket {
+            capacity: capacity,
+            tokens: capacity,
+            rate: rate,
+            last: Instant::now(),
+        };
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    pub fn try_take(&mut self, cost: f64) -> bool {
+        self.refill();
+        let cost = cost.min(self.capacity);
+        if self.tokens < cost {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+
+    // gives back what try_take took for something that didn't happen after all
+    pub fn refund(&mut self, cost: f64) {
+        self.refill();
+        self.tokens = (self.tokens + cost.min(self.capacity)).min(self.capacity);
+    }
+
+    // how long until try_take(cost) would succeed
+    pub fn time_until(&mut self, cost: f64) -> Duration {
+        self.refill();
+        let missing = cost.min(self.capacity) - self.tokens;
+        if missing <= 0.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(missing / self.rate)
+    }
+}
+
+fn cost(line: &str) -> f64 {
+    return 1.0 + line.len() as f64 / BYTES_PER_TOKEN;
+}
+
+// chat traffic is queued per target, everything else (PONG, registration,
+// JOIN...) jumps ahead of it and keeps its order
+fn target_of(line: &str) -> Option<String> {
+    let mut parts = line.split_whitespace();
+    match parts.next() {
+        Some("PRIVMSG") | Some("NOTICE") => parts.next().map(String::from),
+        _ => None,
+    }
+}
+
+// Outbound lines waiting for the rate limiter.  PONGs skip it so a busy bot
+// doesn't ping out, other urgent lines go next, the rest are sent round-robin
+// per target so one chatty reply can't starve another channel.
+#[derive(Debug)]
+pub struct OutQueue {
+    pongs: VecDeque<String>,
+    urgent: VecDeque<String>,
+    targets: HashMap<String, VecDeque<String>>,
+    order: VecDeque<String>,
+    bucket: TokenBucket,
+}
+
+impl OutQueue {
+    pub fn new() -> OutQueue {
+        return OutQueue {
+            pongs: VecDeque::new(),
+            urgent: VecDeque::new(),
+            targets: HashMap::new(),
+            order: VecDeque::new(),
+            bucket: TokenBucket::new(BURST, REFILL_PER_SEC),
+        };
+    }
+
+    pub fn push(&mut self, line: String) {
+        if line.starts_with("PONG ") {
+            self.pongs.push_back(line);
+            return;
+        }
+        let target = match target_of(&line) {
+            Some(target) => target,
+            None => {
+                self.urgent.push_back(line);
+                return;
+            }
+        };
+
+        let lines = self.targets.entry(target.clone()).or_insert_with(VecDeque::new);
+        if lines.is_empty() {
+            self.order.push_back(target);
+        }
+        lines.push_back(line);
+    }
+
+    fn peek(&self) -> Option<&String> {
+        if let Some(line) = self.pongs.front() {
+            return Some(line);
+        }
+        if let Some(line) = self.urgent.front() {
+            return Some(line);
+        }
+        let target = self.order.front()?;
+        return self.targets.get(target).and_then(|lines| lines.front());
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        if let Some(line) = self.pongs.pop_front() {
+            return Some(line);
+        }
+        if let Some(line) = self.urgent.pop_front() {
+            return Some(line);
+        }
+
+        let target = self.order.pop_front()?;
+        let lines = self.targets.get_mut(&target)?;
+        let line = lines.pop_front();
+        if lines.is_empty() {
+            self.targets.remove(&target);
+        } else {
+            self.order.push_back(target);
+        }
+        line
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.peek().is_none();
+    }
+
+    // next line the rate limiter allows, if any
+    pub fn next_ready(&mut self) -> Option<String> {
+        if let Some(pong) = self.pongs.pop_front() {
+            return Some(pong);
+        }
+        let cost = cost(self.peek()?);
+        if !self.bucket.try_take(cost) {
+            return None;
+        }
+        self.pop()
+    }
+
+    // everything, ignoring the rate limiter, for use when disconnecting
+    pub fn drain(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(line) = self.pop() {
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn privmsg(target: &str, text: &str) -> String {
+        return format!("PRIVMSG {} :{}", target, text);
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let mut bucket = TokenBucket::new(3.0, 1.0);
+        assert!(bucket.try_take(1.0));
+        assert!(bucket.try_take(1.0));
+        assert!(bucket.try_take(1.0));
+        assert!(!bucket.try_take(1.0));
+        assert!(bucket.time_until(1.0) > Duration::from_millis(900));
+
+        bucket.last -= Duration::from_secs(2);
+        assert_eq!(bucket.time_until(1.0), Duration::from_secs(0));
+        assert!(bucket.try_take(2.0));
+        assert!(!bucket.try_take(1.0));
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_capacity() {
+        let mut bucket = TokenBucket::new(2.0, 1.0);
+        bucket.last -= Duration::from_secs(60);
+        assert!(bucket.try_take(2.0));
+        assert!(!bucket.try_take(1.0));
+    }
+
+    #[test]
+    fn bucket_caps_cost_at_capacity() {
+        let mut bucket = TokenBucket::new(2.0, 1.0);
+        assert_eq!(bucket.time_until(5.0), Duration::from_secs(0));
+        assert!(bucket.try_take(5.0));
+    }
+
+    #[test]
+    fn bucket_refunds_up_to_capacity() {
+        let mut bucket = TokenBucket::new(2.0, 0.001);
+        assert!(bucket.try_take(2.0));
+        bucket.refund(1.0);
+        assert!(bucket.try_take(1.0));
+        assert!(!bucket.try_take(1.0));
+        bucket.refund(5.0);
+        assert!(bucket.try_take(2.0));
+        assert!(!bucket.try_take(1.0));
+    }
+
+    #[test]
+    fn long_lines_cost_more() {
+        assert_eq!(cost(""), 1.0);
+        assert_eq!(cost(&"x".repeat(512)), 3.0);
+    }
+
+    #[test]
+    fn round_robins_targets_after_urgent_lines() {
+        let mut queue = OutQueue::new();
+        queue.push(privmsg("#a", "1"));
+        queue.push(privmsg("#a", "2"));
+        queue.push(privmsg("#a", "3"));
+        queue.push(privmsg("#b", "1"));
+        queue.push(String::from("JOIN #c"));
+        queue.push(String::from("NOTICE bob :1"));
+        assert_eq!(
+            queue.drain(),
+            vec![
+                String::from("JOIN #c"),
+                privmsg("#a", "1"),
+                privmsg("#b", "1"),
+                String::from("NOTICE bob :1"),
+                privmsg("#a", "2"),
+                privmsg("#a", "3"),
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn rate_limits_chat() {
+        let mut queue = OutQueue::new();
+        queue.bucket = TokenBucket::new(3.0, 0.001);
+        queue.push(privmsg("#a", "1"));
+        queue.push(privmsg("#a", "2"));
+        queue.push(privmsg("#a", "3"));
+        assert_eq!(queue.next_ready(), Some(privmsg("#a", "1")));
+        assert_eq!(queue.next_ready(), Some(privmsg("#a", "2")));
+        assert_eq!(queue.next_ready(), None);
+        assert!(!queue.is_empty());
+    }
+
+    #[test]
+    fn pong_skips_drained_bucket() {
+        let mut queue = OutQueue::new();
+        queue.bucket = TokenBucket::new(1.0, 0.001);
+        queue.push(privmsg("#a", "1"));
+        queue.push(privmsg("#a", "2"));
+        assert_eq!(queue.next_ready(), Some(privmsg("#a", "1")));
+        queue.push(String::from("JOIN #b"));
+        queue.push(String::from("PONG :server"));
+        assert_eq!(queue.next_ready(), Some(String::from("PONG :server")));
+        assert_eq!(queue.next_ready(), None);
+        assert_eq!(queue.drain(), vec![String::from("JOIN #b"), privmsg("#a", "2")]);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::queue::TokenBucket;
+
+// external API calls per minute across every network
+pub const DEFAULT_HTTP_PER_MINUTE: u32 = 30;
+
+// shared by every network, worker commands each take one call from it
+pub type HttpBudget = Arc<Mutex<TokenBucket>>;
+
+pub fn http_budget(per_minute: u32) -> HttpBudget {
+    let per_minute = per_minute.max(1) as f64;
+    return Arc::new(Mutex::new(TokenBucket::new(per_minute, per_minute / 60.0)));
+}
+
+// seconds that must pass between commands, 0 turns a limit off
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cooldowns {
+    // between any two commands from one user@host
+    pub user: u64,
+    // between any two commands in one channel
+    pub channel: u64,
+    // between uses of a command in one channel, by command name
+    pub commands: HashMap<String, u64>,
+}
+
+impl Default for Cooldowns {
+    fn default() -> Cooldowns {
+        return Cooldowns {
+            user: 3,
+            channel: 1,
+            commands: HashMap::new(),
+        };
+    }
+}
+
+// When users and channels last ran commands. Keys are casefolded by the caller.
+#[derive(Debug)]
+pub struct RateLimiter {
+    cooldowns: Cooldowns,
+    users: HashMap<String, Instant>,
+    channels: HashMap<String, Instant>,
+    commands: HashMap<(String, String), Instant>,
+    // users already told to slow down, and until when we stay quiet
+    warned: HashMap<String, Instant>,
+}
+
+fn remaining(last: Option<&Instant>, seconds: u64, now: Instant) -> Duration {
+    match last {
+        Some(last) => (*last + Duration::from_secs(seconds)).saturating_duration_since(now),
+        None => Duration::from_secs(0),
+    }
+}
+
+impl RateLimiter {
+    pub fn new(cooldowns: Cooldowns) -> RateLimiter {
+        return RateLimiter {
+            cooldowns: cooldowns,
+            users: HashMap::new(),
+            channels: HashMap::new(),
+            commands: HashMap::new(),
+            warned: HashMap::new(),
+        };
+    }
+
+    pub fn set_cooldowns(&mut self, cooldowns: Cooldowns) {
+        self.cooldowns = cooldowns;
+    }
+
+    // how long until user may run command in channel, None if they may now
+    pub fn wait(&self, user: &str, channel: &str, command: &str, now: Instant) -> Option<Duration> {
+        let command_cooldown = self.cooldowns.commands.get(command).copied().unwrap_or(0);
+        let key = (String::from(command), String::from(channel));
+        let wait = remaining(self.users.get(user), self.cooldowns.user, now)
+            .max(remaining(self.channels.get(channel), self.cooldowns.channel, now))
+            .max(remaining(self.commands.get(&key), command_cooldown, now));
+        if wait.as_millis() == 0 {
+            return None;
+        }
+        Some(wait)
+    }
+
+    pub fn record(&mut self, user: &str, channel: &str, command: &str, now: Instant) {
+        self.prune(now);
+        self.users.insert(String::from(user), now);
+        self.channels.insert(String::from(channel), now);
+        self.commands.insert((String::from(command), String::from(channel)), now);
+        self.warned.remove(user);
+    }
+
+    // true only the first time a user is held back, so they hear about it once
+    pub fn warn(&mut self, user: &str, wait: Duration, now: Instant) -> bool {
+        if let Some(quiet_until) = self.warned.get(user) {
+            if *quiet_until > now {
+                return false;
+            }
+        }
+        self.warned.insert(String::from(user), now + wait);
+        true
+    }
+
+    // forgets anything older than the longest cooldown
+    fn prune(&mut self, now: Instant) {
+        let longest = self.cooldowns.commands.values().copied().chain([self.cooldowns.user, self.cooldowns.channel]).max();
+        let horizon = Duration::from_secs(longest.unwrap_or(0));
+        let fresh = |last: &Instant| now.saturating_duration_since(*last) < horizon;
+        self.users.retain(|_, last| fresh(last));
+        self.channels.retain(|_, last| fresh(last));
+        self.commands.retain(|_, last| fresh(last));
+        self.warned.retain(|_, until| *until > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.commands.insert(String::from("weather"), 30);
+        return RateLimiter::new(cooldowns);
+    }
+
+    fn secs(seconds: u64) -> Duration {
+        return Duration::from_secs(seconds);
+    }
+
+    #[test]
+    fn nothing_recorded_means_no_wait() {
+        let limiter = limiter();
+        assert_eq!(limiter.wait("al@host", "#rust", "ud", Instant::now()), None);
+    }
+
+    #[test]
+    fn user_cooldown_follows_them_across_channels() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.record("al@host", "#rust", "ud", now);
+        assert_eq!(limiter.wait("al@host", "#other", "ud", now + secs(1)), Some(secs(2)));
+        assert_eq!(limiter.wait("al@host", "#other", "ud", now + secs(3)), None);
+    }
+
+    #[test]
+    fn channel_cooldown_holds_everyone_back() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.record("al@host", "#rust", "ud", now);
+        assert_eq!(limiter.wait("bob@host", "#rust", "ud", now), Some(secs(1)));
+        assert_eq!(limiter.wait("bob@host", "#rust", "ud", now + secs(1)), None);
+        assert_eq!(limiter.wait("bob@host", "#other", "ud", now), None);
+    }
+
+    #[test]
+    fn command_cooldown_is_per_channel() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.record("al@host", "#rust", "weather", now);
+        assert_eq!(limiter.wait("bob@host", "#rust", "weather", now + secs(10)), Some(secs(20)));
+        assert_eq!(limiter.wait("bob@host", "#rust", "ud", now + secs(10)), None);
+        assert_eq!(limiter.wait("bob@host", "#other", "weather", now + secs(10)), None);
+        assert_eq!(limiter.wait("bob@host", "#rust", "weather", now + secs(30)), None);
+    }
+
+    #[test]
+    fn zero_turns_a_cooldown_off() {
+        let mut limiter = RateLimiter::new(Cooldowns {
+            user: 0,
+            channel: 0,
+            commands: HashMap::new(),
+        });
+        let now = Instant::now();
+        limiter.record("al@host", "#rust", "ud", now);
+        assert_eq!(limiter.wait("al@host", "#rust", "ud", now), None);
+    }
+
+    #[test]
+    fn warns_once_per_wait() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        assert!(limiter.warn("al@host", secs(3), now));
+        assert!(!limiter.warn("al@host", secs(3), now + secs(1)));
+        assert!(limiter.warn("bob@host", secs(3), now + secs(1)));
+        assert!(limiter.warn("al@host", secs(3), now + secs(3)));
+    }
+
+    #[test]
+    fn running_a_command_resets_the_warning() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        assert!(limiter.warn("al@host", secs(60), now));
+        limiter.record("al@host", "#rust", "ud", now + secs(1));
+        assert!(limiter.warn("al@host", secs(3), now + secs(2)));
+    }
+
+    #[test]
+    fn prunes_entries_past_the_longest_cooldown() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.record("al@host", "#rust", "weather", now);
+        limiter.warn("bob@host", secs(3), now);
+        limiter.prune(now + secs(29));
+        assert_eq!(limiter.users.len(), 1);
+        assert_eq!(limiter.commands.len(), 1);
+        assert_eq!(limiter.warned.len(), 0);
+
+        limiter.prune(now + secs(30));
+        assert!(limiter.users.is_empty());
+        assert!(limiter.channels.is_empty());
+        assert!(limiter.commands.is_empty());
+    }
+
+    #[test]
+    fn budget_spreads_calls_over_a_minute() {
+        let budget = http_budget(2);
+        let mut budget = budget.lock().unwrap();
+        assert!(budget.try_take(1.0));
+        assert!(budget.try_take(1.0));
+        assert!(!budget.try_take(1.0));
+        assert!(budget.time_until(1.0) > secs(29));
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::{IrcBot, IrcConnection, IrcMessage, Result};
+
+// Why a connection ended, which decides how long to wait before the next one.
+#[derive(Debug, Clone)]
+pub enum Disconnect {
+    // TCP/TLS connect failed
+    Connect(String),
+    // connection reset, read error or EOF
+    Io(String),
+    // the server sent ERROR
+    ServerError(String),
+    // K/G-lines, 465 ERR_YOUREBANNEDCREEP
+    Banned(String),
+    // sasl rejected our credentials
+    Auth(String),
+    // no traffic from the server within the timeout
+    Timeout(String),
+}
+
+impl Disconnect {
+    fn base_delay(&self) -> Duration {
+        match self {
+            Disconnect::Connect(_) | Disconnect::Io(_) | Disconnect::Timeout(_) => Duration::from_secs(5),
+            Disconnect::ServerError(_) | Disconnect::Auth(_) => Duration::from_secs(30),
+            Disconnect::Banned(_) => Duration::from_secs(600),
+        }
+    }
+
+    fn max_delay(&self) -> Duration {
+        match self {
+            Disconnect::Banned(_) => Duration::from_secs(4 * 3600),
+            _ => Duration::from_secs(600),
+        }
+    }
+}
+
+impl fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Disconnect::Connect(reason) => write!(f, "connect failed: {}", reason),
+            Disconnect::Io(reason) => write!(f, "connection lost: {}", reason),
+            Disconnect::ServerError(reason) => write!(f, "server error: {}", reason),
+            Disconnect::Banned(reason) => write!(f, "banned: {}", reason),
+            Disconnect::Auth(reason) => write!(f, "authentication failed: {}", reason),
+            Disconnect::Timeout(reason) => write!(f, "timed out: {}", reason),
+        }
+    }
+}
+
+fn is_ban(reason: &str) -> bool {
+    let reason = reason.to_lowercase();
+    return ["k-lined", "g-lined", "z-lined", "d-lined", "banned"]
+        .iter()
+        .any(|ban| reason.contains(ban));
+}
+
+// ERROR :Closing Link: ... (K-Lined)
+pub fn on_error(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let reason = msg.args.last().cloned().unwrap_or_default();
+    if is_ban(&reason) {
+        bot.abort(Disconnect::Banned(reason), false);
+    } else {
+        bot.abort(Disconnect::ServerError(reason), false);
+    }
+    Ok(())
+}
+
+// 465 ERR_YOUREBANNEDCREEP
+pub fn on_banned(bot: &mut IrcBot, _stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let reason = msg.args.last().cloned().unwrap_or_default();
+    bot.abort(Disconnect::Banned(reason), false);
+    Ok(())
+}
+
+// Exponential backoff with jitter over a rotating list of servers.
+#[derive(Debug)]
+pub struct Reconnect {
+    servers: Vec<String>,
+    next_server: usize,
+    attempt: u32,
+}
+
+impl Reconnect {
+    pub fn new(servers: Vec<String>) -> Reconnect {
+        return Reconnect {
+            servers: servers,
+            next_server: 0,
+            attempt: 0,
+        };
+    }
+
+    pub fn next_server(&mut self) -> String {
+        let server = self.servers[self.next_server % self.servers.len()].clone();
+        self.next_server += 1;
+        server
+    }
+
+    // a connection that made it through registration starts the backoff over
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn delay(&mut self, reason: &Disconnect) -> Duration {
+        let exponent = self.attempt.min(16);
+        self.attempt += 1;
+
+        let delay = reason.base_delay().saturating_mul(1 << exponent).min(reason.max_delay());
+        // anywhere between half and the full delay so restarts don't stampede
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        delay.mul_f64(jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io() -> Disconnect {
+        return Disconnect::Io(String::from("reset"));
+    }
+
+    #[test]
+    fn delay_doubles_within_jitter() {
+        let mut reconnect = Reconnect::new(vec![String::from("irc.example.org:6697")]);
+        for attempt in 0..4 {
+            let full = Duration::from_secs(5 << attempt);
+            let delay = reconnect.delay(&io());
+            assert!(delay >= full / 2 && delay <= full, "attempt {}: {:?}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let mut reconnect = Reconnect::new(vec![String::from("irc.example.org:6697")]);
+        for _ in 0..40 {
+            assert!(reconnect.delay(&io()) <= Duration::from_secs(600));
+        }
+        let banned = Disconnect::Banned(String::from("K-Lined"));
+        assert!(reconnect.delay(&banned) <= Duration::from_secs(4 * 3600));
+        assert!(reconnect.delay(&banned) >= Duration::from_secs(2 * 3600));
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut reconnect = Reconnect::new(vec![String::from("irc.example.org:6697")]);
+        for _ in 0..10 {
+            reconnect.delay(&io());
+        }
+        reconnect.reset();
+        assert!(reconnect.delay(&io()) <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn worse_reasons_wait_longer() {
+        let io = io();
+        let error = Disconnect::ServerError(String::from("Closing Link"));
+        let banned = Disconnect::Banned(String::from("K-Lined"));
+        assert!(io.base_delay() < error.base_delay());
+        assert!(error.base_delay() < banned.base_delay());
+        // even with the least and most jitter
+        let mut reconnect = Reconnect::new(vec![String::from("irc.example.org:6697")]);
+        assert!(reconnect.delay(&io) <= Duration::from_secs(5));
+        reconnect.reset();
+        assert!(reconnect.delay(&error) >= Duration::from_secs(15));
+        reconnect.reset();
+        assert!(reconnect.delay(&banned) >= Duration::from_secs(300));
+    }
+
+    #[test]
+    fn rotates_servers() {
+        let mut reconnect = Reconnect::new(vec![String::from("a:6697"), String::from("b:6697")]);
+        assert_eq!(reconnect.next_server(), "a:6697");
+        assert_eq!(reconnect.next_server(), "b:6697");
+        assert_eq!(reconnect.next_server(), "a:6697");
+    }
+
+    #[test]
+    fn recognises_bans() {
+        assert!(is_ban("Closing Link: rusty[example.org] (K-Lined)"));
+        assert!(is_ban("G-lined: spam"));
+        assert!(is_ban("You are banned from this server"));
+        assert!(is_ban("Z-Lined"));
+        assert!(!is_ban("Closing Link: rusty[example.org] (Ping timeout: 240 seconds)"));
+        assert!(!is_ban("Closing Link: rusty[example.org] (Quit: bye)"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::config::{Config, NetworkConfig};
+use crate::ignore;
+use crate::lag::{DEFAULT_PING_INTERVAL, DEFAULT_PING_TIMEOUT};
+use crate::split::DEFAULT_MAX_LINES;
+use crate::{default_greetings, join, load_config, notice, part, Error, IrcBot, IrcConnection, Result};
+
+// sent by !reload, answered with the error if the config couldn't be loaded
+#[derive(Debug)]
+pub struct ReloadRequest {
+    pub network: String,
+    pub nick: String,
+    pub done: oneshot::Sender<Result<()>>,
+}
+
+#[derive(Debug)]
+pub struct Reload {
+    pub config: Config,
+    // network name and nick to tell what changed
+    pub requested_by: Option<(String, String)>,
+}
+
+impl Reload {
+    pub fn new(config: Config) -> Reload {
+        return Reload {
+            config: config,
+            requested_by: None,
+        };
+    }
+}
+
+// Re-reads the config on SIGHUP or !reload and hands it to every network.
+pub async fn listen(
+    args: ArgMatches,
+    configs: watch::Sender<Arc<Reload>>,
+    mut requests: mpsc::UnboundedReceiver<ReloadRequest>,
+) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => Some(hangups),
+        Err(e) => {
+            log::warn!("can't listen for SIGHUP: {}", e);
+            None
+        }
+    };
+
+    loop {
+        let request = tokio::select! {
+            Some(_) = async { hangups.as_mut()?.recv().await } => None,
+            request = requests.recv() => match request {
+                Some(request) => Some(request),
+                None => return,
+            },
+        };
+
+        let result = reload(&args, &configs, &request);
+        if let Err(e) = &result {
+            log::error!("reload failed: {}", e);
+        }
+        if let Some(request) = request {
+            let _ = request.done.send(result);
+        }
+    }
+}
+
+fn reload(args: &ArgMatches, configs: &watch::Sender<Arc<Reload>>, request: &Option<ReloadRequest>) -> Result<()> {
+    if !args.is_present("config") {
+        return Err(Box::new(Error::new("no config file to reload, start with --config")));
+    }
+    let config = load_config(args)?;
+    log::info!("reloading {}", args.value_of("config").unwrap_or(""));
+
+    for network in &config.networks {
+        if !configs.borrow().config.networks.iter().any(|n| n.name() == network.name()) {
+            log::warn!("{} was added, restart to connect to it", network.name());
+        }
+    }
+    if config.http_per_minute != configs.borrow().config.http_per_minute {
+        log::warn!("http_per_minute changed, restart to apply it");
+    }
+
+    let _ = configs.send(Arc::new(Reload {
+        config: config,
+        requested_by: request.as_ref().map(|r| (r.network.clone(), r.nick.clone())),
+    }));
+    Ok(())
+}
+
+// Applies what can change without reconnecting, and says what can't.
+pub fn apply(bot: &mut IrcBot, stream: &mut IrcConnection, network: &mut NetworkConfig, reload: &Reload) -> Result<()> {
+    let name = network.name();
+    let requester = match &reload.requested_by {
+        Some((requested_on, nick)) if *requested_on == name => Some(nick.clone()),
+        _ => None,
+    };
+
+    let new = match reload.config.networks.iter().find(|n| n.name() == name) {
+        Some(new) => new.clone(),
+        None => {
+            log::warn!("{} is no longer configured, restart to disconnect", name);
+            return Ok(());
+        }
+    };
+
+    let mut changed = Vec::new();
+    let casemapping = bot.isupport.casemapping;
+    for channel in &new.channels {
+        if !network.channels.iter().any(|c| casemapping.equals(&c.name, &channel.name)) {
+            if bot.registered {
+                join(stream, &channel.name, &channel.key)?;
+            }
+            changed.push(format!("+{}", channel.name));
+        }
+    }
+    for channel in &network.channels {
+        if !new.channels.iter().any(|c| casemapping.equals(&c.name, &channel.name)) {
+            if bot.registered {
+                part(stream, &channel.name, &String::from("reloaded"))?;
+            }
+            changed.push(format!("-{}", channel.name));
+        }
+    }
+    bot.channels = new.channels.clone();
+
+    if new.ignore != network.ignore {
+        ignore::set_config_rules(bot, &new.ignore);
+        changed.push(String::from("ignore"));
+    }
+    if new.owners != network.owners {
+        bot.owners = new.owners.clone();
+        changed.push(String::from("owners"));
+    }
+    if new.greetings != network.greetings {
+        bot.set_greetings(new.greetings.clone().unwrap_or_else(default_greetings));
+        changed.push(String::from("greetings"));
+    }
+    if new.commands != network.commands {
+        bot.commands = new.commands.clone();
+        changed.push(String::from("commands"));
+    }
+    if new.alt_nicks != network.alt_nicks {
+        bot.set_alt_nicks(new.alt_nicks.clone());
+        changed.push(String::from("alt_nicks"));
+    }
+    if new.cooldowns != network.cooldowns {
+        bot.limiter.set_cooldowns(new.cooldowns.clone());
+        changed.push(String::from("cooldowns"));
+    }
+    if new.max_lines != network.max_lines {
+        stream.max_lines = new.max_lines.unwrap_or(DEFAULT_MAX_LINES);
+        changed.push(String::from("max_lines"));
+    }
+    if new.ping_interval != network.ping_interval || new.ping_timeout != network.ping_timeout {
+        bot.set_ping(
+            Duration::from_secs(new.ping_interval.unwrap_or(DEFAULT_PING_INTERVAL)),
+            Duration::from_secs(new.ping_timeout.unwrap_or(DEFAULT_PING_TIMEOUT)),
+        );
+        changed.push(String::from("ping"));
+    }
+
+    let api_keys = reload.config.api_keys.clone().with_env();
+    if api_keys != bot.api_keys {
+        bot.api_keys = api_keys;
+        changed.push(String::from("api_keys"));
+    }
+
+    // only read while connecting
+    let mut restart = Vec::new();
+    if new.host != network.host || new.servers != network.servers {
+        restart.push("servers");
+    }
+    if new.nick != network.nick {
+        restart.push("nick");
+    }
+    if new.realname != network.realname {
+        restart.push("realname");
+    }
+    if new.caps != network.caps {
+        restart.push("caps");
+    }
+    if new.sasl != network.sasl || new.sasl_credentials != network.sasl_credentials {
+        restart.push("sasl");
+    }
+    if new.no_tls != network.no_tls || new.tls != network.tls {
+        restart.push("tls");
+    }
+
+    let mut report = match changed.len() {
+        0 => String::from("reloaded, nothing changed"),
+        _ => format!("reloaded: {}", changed.join(" ")),
+    };
+    if restart.len() > 0 {
+        report.push_str(&format!("; restart to apply {}", restart.join(" ")));
+    }
+    log::info!("{}: {}", name, report);
+    if let Some(nick) = requester {
+        notice(stream, &nick, &report)?;
+    }
+
+    // what isn't applied yet stays as it was, so it's reported again next time
+    let mut applied = new;
+    applied.host = network.host.clone();
+    applied.servers = network.servers.clone();
+    applied.nick = network.nick.clone();
+    applied.realname = network.realname.clone();
+    applied.caps = network.caps.clone();
+    applied.sasl = network.sasl.clone();
+    applied.sasl_credentials = network.sasl_credentials.clone();
+    applied.no_tls = network.no_tls;
+    applied.tls = network.tls.clone();
+    *network = applied;
+    Ok(())
+}
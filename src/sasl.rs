@@ -0,0 +1,198 @@
+use std::env;
+use std::fs;
+
+use data_encoding::BASE64;
+
+use crate::reconnect::Disconnect;
+use crate::{caps, send, Error, IrcBot, IrcConnection, IrcMessage, Result};
+
+// AUTHENTICATE payloads are sent in chunks of at most 400 bytes
+const CHUNK_SIZE: usize = 400;
+const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub enum Mechanism {
+    Plain { account: String, password: String },
+    External,
+}
+
+impl Mechanism {
+    pub fn name(&self) -> &str {
+        match self {
+            Mechanism::Plain { .. } => "PLAIN",
+            Mechanism::External => "EXTERNAL",
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Mechanism::Plain { account, password } => {
+                format!("{}\0{}\0{}", account, account, password).into_bytes()
+            }
+            Mechanism::External => Vec::new(),
+        }
+    }
+}
+
+// reads account and password from RUSTY_SASL_ACCOUNT and RUSTY_SASL_PASSWORD
+pub fn plain_from_env() -> Result<Mechanism> {
+    let account = env::var("RUSTY_SASL_ACCOUNT")?;
+    let password = env::var("RUSTY_SASL_PASSWORD")?;
+    Ok(Mechanism::Plain { account, password })
+}
+
+// reads `account = ...` and `password = ...` lines from a credentials file
+pub fn plain_from_file(path: &str) -> Result<Mechanism> {
+    let contents = fs::read_to_string(path)?;
+    let (mut account, mut password) = (None, None);
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, "=");
+        let key = parts.next().unwrap_or("").trim();
+        let value = String::from(parts.next().unwrap_or("").trim());
+        match key {
+            "account" => account = Some(value),
+            "password" => password = Some(value),
+            _ => {}
+        }
+    }
+
+    match (account, password) {
+        (Some(account), Some(password)) => Ok(Mechanism::Plain { account, password }),
+        _ => Err(Box::new(Error::new(&format!(
+            "{} must set both account and password",
+            path
+        )))),
+    }
+}
+
+pub fn start(bot: &mut IrcBot, stream: &mut IrcConnection) -> Result<()> {
+    if let Some(mechanism) = &bot.sasl {
+        log::info!("authenticating with {}", mechanism.name());
+        send(stream, &format!("AUTHENTICATE {}", mechanism.name()))?;
+    }
+    Ok(())
+}
+
+// a rejected login drops the connection; after MAX_ATTEMPTS in a row we stop reconnecting
+pub fn fail(bot: &mut IrcBot, stream: &mut IrcConnection, reason: &str) -> Result<()> {
+    bot.sasl_failures += 1;
+    log::error!("sasl authentication failed: {} (attempt {})", reason, bot.sasl_failures);
+
+    send(stream, &String::from("QUIT :sasl authentication failed"))?;
+    bot.abort(Disconnect::Auth(String::from(reason)), bot.sasl_failures >= MAX_ATTEMPTS);
+    Ok(())
+}
+
+pub fn on_authenticate(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    if msg.args.len() < 1 || msg.args[0] != "+" {
+        return Ok(());
+    }
+
+    let payload = match &bot.sasl {
+        Some(mechanism) => BASE64.encode(&mechanism.payload()),
+        None => return Ok(()),
+    };
+
+    let mut chunks = payload.as_bytes().chunks(CHUNK_SIZE).peekable();
+    if chunks.peek().is_none() {
+        send(stream, &String::from("AUTHENTICATE +"))?;
+    }
+    let mut last_len = 0;
+    for chunk in chunks {
+        last_len = chunk.len();
+        send(stream, &format!("AUTHENTICATE {}", String::from_utf8_lossy(chunk)))?;
+    }
+    if last_len == CHUNK_SIZE {
+        send(stream, &String::from("AUTHENTICATE +"))?;
+    }
+    Ok(())
+}
+
+// 900 RPL_LOGGEDIN, 903 RPL_SASLSUCCESS, 902/904/905/906/907 failures, 908 RPL_SASLMECHS
+pub fn on_numeric(bot: &mut IrcBot, stream: &mut IrcConnection, msg: &IrcMessage) -> Result<()> {
+    let text = msg.args.last().cloned().unwrap_or_default();
+    match msg.command.as_str() {
+        "900" => {
+            if msg.args.len() > 2 {
+                log::info!("logged in as {}", msg.args[2]);
+            }
+        }
+        "903" | "907" => {
+            bot.sasl_failures = 0;
+            caps::end(bot, stream)?;
+        }
+        "908" => {
+            if msg.args.len() > 1 {
+                log::warn!("server supports sasl mechanisms: {}", msg.args[1]);
+            }
+            // retrying an unsupported mechanism won't help
+            bot.sasl_failures = MAX_ATTEMPTS - 1;
+        }
+        "902" | "904" | "905" | "906" => fail(bot, stream, &text)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{bot, connection, message, sent};
+
+    // PLAIN sends account\0account\0password
+    fn plain(account: &str, password_len: usize) -> Mechanism {
+        return Mechanism::Plain {
+            account: String::from(account),
+            password: "p".repeat(password_len),
+        };
+    }
+
+    fn authenticate(mechanism: Mechanism) -> Vec<String> {
+        let mut bot = bot();
+        bot.set_sasl(mechanism);
+        let (mut stream, mut lines) = connection();
+        on_authenticate(&mut bot, &mut stream, &message("AUTHENTICATE +")).unwrap();
+        return sent(&mut lines);
+    }
+
+    #[test]
+    fn sends_short_payloads_in_one_line() {
+        let lines = authenticate(plain("rusty", 7));
+        assert_eq!(lines, vec![format!("AUTHENTICATE {}", BASE64.encode(b"rusty\0rusty\0ppppppp"))]);
+    }
+
+    #[test]
+    fn ends_a_full_last_chunk_with_a_plus() {
+        // 300 bytes encode to exactly 400
+        let lines = authenticate(plain("rusty", 300 - 12));
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + CHUNK_SIZE);
+        assert_eq!(lines[1], "AUTHENTICATE +");
+    }
+
+    #[test]
+    fn splits_long_payloads() {
+        let lines = authenticate(plain("rusty", 301 - 12));
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + CHUNK_SIZE);
+        assert_eq!(lines[1].len(), "AUTHENTICATE ".len() + 4);
+    }
+
+    #[test]
+    fn sends_an_empty_payload_as_a_plus() {
+        assert_eq!(authenticate(Mechanism::External), vec!["AUTHENTICATE +"]);
+    }
+
+    #[test]
+    fn gives_up_after_repeated_failures() {
+        let mut bot = bot();
+        bot.set_sasl(plain("rusty", 7));
+        let (mut stream, mut lines) = connection();
+        for attempt in 1..=MAX_ATTEMPTS {
+            on_numeric(&mut bot, &mut stream, &message(":server 904 rusty :SASL authentication failed")).unwrap();
+            assert!(matches!(bot.abort.take(), Some(Disconnect::Auth(_))));
+            assert_eq!(bot.fatal, attempt == MAX_ATTEMPTS);
+        }
+        assert_eq!(sent(&mut lines).len(), MAX_ATTEMPTS as usize);
+    }
+}
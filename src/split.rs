@@ -0,0 +1,145 @@
+extern crate unicode_segmentation;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+pub const DEFAULT_MAX_LINES: usize = 4;
+
+static TRUNCATED: &str = " (truncated)";
+
+// breaks a word that can't fit on one line without splitting grapheme clusters
+fn split_word(word: &str, budget: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for grapheme in word.graphemes(true) {
+        if chunk.len() + grapheme.len() > budget && chunk.len() > 0 {
+            chunks.push(chunk);
+            chunk = String::new();
+        }
+        chunk.push_str(grapheme);
+    }
+    if chunk.len() > 0 {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+fn wrap(text: &str, budget: usize, lines: &mut Vec<String>) {
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let needed = if line.len() > 0 { line.len() + 1 + word.len() } else { word.len() };
+        if needed <= budget {
+            if line.len() > 0 {
+                line.push(' ');
+            }
+            line.push_str(word);
+            continue;
+        }
+
+        if line.len() > 0 {
+            lines.push(line);
+        }
+
+        let mut chunks = split_word(word, budget);
+        line = chunks.pop().unwrap_or_default();
+        lines.extend(chunks);
+    }
+    if line.len() > 0 {
+        lines.push(line);
+    }
+}
+
+// Splits text into lines of at most `budget` bytes, breaking on newlines and
+// word boundaries.  Anything past `max_lines` is cut and marked as truncated.
+pub fn split_message(text: &str, budget: usize, max_lines: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        wrap(paragraph, budget, &mut lines);
+    }
+
+    if lines.len() > max_lines && max_lines > 0 {
+        lines.truncate(max_lines);
+        let last = lines.pop().unwrap_or_default();
+        let mut graphemes: Vec<&str> = last.graphemes(true).collect();
+        while graphemes.len() > 0 && graphemes.concat().len() + TRUNCATED.len() > budget {
+            graphemes.pop();
+        }
+        let mut last = String::from(graphemes.concat().trim_end());
+        last.push_str(TRUNCATED);
+        lines.push(String::from(last.trim_start()));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_sends_nothing() {
+        assert!(split_message("", 10, DEFAULT_MAX_LINES).is_empty());
+        assert!(split_message(" \n\n  ", 10, DEFAULT_MAX_LINES).is_empty());
+    }
+
+    #[test]
+    fn wraps_on_words_and_newlines() {
+        assert_eq!(split_message("one two three", 7, 0), vec!["one two", "three"]);
+        assert_eq!(split_message("one\ntwo", 100, 0), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn fills_lines_exactly_to_budget() {
+        assert_eq!(split_message("abc def", 7, 0), vec!["abc def"]);
+        assert_eq!(split_message("abc defg", 7, 0), vec!["abc", "defg"]);
+    }
+
+    #[test]
+    fn breaks_words_longer_than_budget() {
+        assert_eq!(split_message("abcdefghij", 4, 0), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(split_message("x abcdefghij y", 4, 0), vec!["x", "abcd", "efgh", "ij y"]);
+    }
+
+    #[test]
+    fn keeps_multibyte_chars_whole_at_budget() {
+        // é is two bytes, so the fourth one doesn't fit in 7
+        let lines = split_message("éééééé", 7, 0);
+        assert_eq!(lines, vec!["ééé", "ééé"]);
+        for line in &lines {
+            assert!(line.len() <= 7);
+        }
+    }
+
+    #[test]
+    fn keeps_graphemes_whole_at_budget() {
+        // e and a combining acute accent, 3 bytes together
+        let lines = split_message("e\u{301}e\u{301}e\u{301}", 7, 0);
+        assert_eq!(lines, vec!["e\u{301}e\u{301}", "e\u{301}"]);
+
+        // a family emoji is one 18 byte grapheme, wider than the budget
+        let family = "👨\u{200d}👩\u{200d}👧";
+        assert_eq!(split_message(&format!("a{}", family), 10, 0), vec!["a", family]);
+    }
+
+    #[test]
+    fn truncates_past_max_lines() {
+        let lines = split_message("one\ntwo\nthree\nfour", 20, 2);
+        assert_eq!(lines, vec!["one", "two (truncated)"]);
+    }
+
+    #[test]
+    fn makes_room_for_truncated_suffix() {
+        let lines = split_message("aaaa bbbb cccc dddd eeee ffff", 16, 1);
+        assert_eq!(lines, vec!["aaaa (truncated)"]);
+        assert!(lines[0].len() <= 16);
+    }
+
+    #[test]
+    fn makes_room_for_suffix_without_splitting_graphemes() {
+        let lines = split_message("ééééééééé\nmore", 16, 1);
+        assert_eq!(lines, vec!["éé (truncated)"]);
+    }
+
+    #[test]
+    fn leaves_short_messages_alone() {
+        assert_eq!(split_message("one\ntwo", 20, 2), vec!["one", "two"]);
+    }
+}
@@ -0,0 +1,38 @@
+// Fixtures shared by the unit tests.
+use rusqlite::Connection;
+use tokio::sync::mpsc;
+
+use crate::transport::Outgoing;
+use crate::{parse_message, IrcBot, IrcConnection, IrcMessage};
+
+// a bot with an empty in-memory database that isn't connected anywhere
+pub fn bot() -> IrcBot {
+    let db = Connection::open_in_memory().unwrap();
+    return IrcBot::new(String::from("irc.example.org:6697"), String::from("rusty"), Vec::new(), db);
+}
+
+pub fn parse(line: &str) -> Option<IrcMessage> {
+    return parse_message(&mut String::from(line));
+}
+
+// for lines the test knows are well formed
+pub fn message(line: &str) -> IrcMessage {
+    return parse(line).unwrap();
+}
+
+// a connection that queues lines for the test to read back with sent()
+pub fn connection() -> (IrcConnection, mpsc::UnboundedReceiver<Outgoing>) {
+    let (outgoing, lines) = mpsc::unbounded_channel();
+    return (IrcConnection::new(outgoing, &String::from("rusty"), 4), lines);
+}
+
+// the lines written since the last call
+pub fn sent(lines: &mut mpsc::UnboundedReceiver<Outgoing>) -> Vec<String> {
+    let mut sent = Vec::new();
+    while let Ok(outgoing) = lines.try_recv() {
+        if let Outgoing::Line(line) = outgoing {
+            sent.push(line);
+        }
+    }
+    return sent;
+}
@@ -0,0 +1,165 @@
+extern crate rustls_pemfile;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use data_encoding::HEXLOWER;
+use ring::digest::{digest, SHA256};
+use rustls::client::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::internal::msgs::handshake::DigitallySignedStruct;
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsOptions {
+    pub insecure: bool,
+    pub ca_file: Option<String>,
+    pub pin_sha256: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+pub fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.len() < 1 {
+        return Err(Box::new(Error::new(&format!("no certificates found in {}", path))));
+    }
+    Ok(certs)
+}
+
+pub fn load_private_key(path: &str) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    Err(Box::new(Error::new(&format!("no private key found in {}", path))))
+}
+
+fn load_roots(ca_file: &Option<String>) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    if let Some(path) = ca_file {
+        let certs: Vec<Vec<u8>> = load_certs(path)?.into_iter().map(|c| c.0).collect();
+        let (added, skipped) = roots.add_parsable_certificates(&certs);
+        log::debug!("loaded {} ca certificates from {} ({} skipped)", added, path, skipped);
+    } else {
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+    }
+    Ok(roots)
+}
+
+// accepts "AB:CD:..." as well as plain hex
+fn parse_fingerprint(s: &str) -> Result<Vec<u8>> {
+    let hex = s.replace(":", "").to_lowercase();
+    let fingerprint = HEXLOWER.decode(hex.as_bytes())?;
+    if fingerprint.len() != 32 {
+        return Err(Box::new(Error::new(&format!("invalid sha256 fingerprint: {}", s))));
+    }
+    Ok(fingerprint)
+}
+
+pub fn client_config(options: &TlsOptions) -> Result<ClientConfig> {
+    let builder = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()?;
+
+    let verifier: Arc<dyn ServerCertVerifier> = if let Some(pin) = &options.pin_sha256 {
+        Arc::new(PinnedCertVerification {
+            fingerprint: parse_fingerprint(pin)?,
+        })
+    } else if options.insecure {
+        log::warn!("tls certificate verification is disabled");
+        Arc::new(NoCertificateVerification {})
+    } else {
+        Arc::new(WebPkiVerifier::new(load_roots(&options.ca_file)?, None))
+    };
+    let builder = builder.with_custom_certificate_verifier(verifier);
+
+    let config = if let Some(cert_path) = &options.client_cert {
+        let certs = load_certs(cert_path)?;
+        let key = load_private_key(options.client_key.as_ref().unwrap_or(cert_path))?;
+        builder.with_single_cert(certs, key)?
+    } else {
+        builder.with_no_client_auth()
+    };
+    Ok(config)
+}
+
+struct NoCertificateVerification {}
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &Certificate,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        return Ok(HandshakeSignatureValid::assertion());
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &Certificate,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        return Ok(HandshakeSignatureValid::assertion());
+    }
+}
+
+// trusts exactly one server certificate, handshake signatures are still checked
+struct PinnedCertVerification {
+    fingerprint: Vec<u8>,
+}
+
+impl ServerCertVerifier for PinnedCertVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = digest(&SHA256, &end_entity.0);
+        if fingerprint.as_ref() != self.fingerprint.as_slice() {
+            return Err(rustls::Error::General(format!(
+                "certificate fingerprint {} does not match pin",
+                HEXLOWER.encode(fingerprint.as_ref())
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+}
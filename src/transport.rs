@@ -0,0 +1,121 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use rustls::ClientConfig;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_rustls::TlsConnector;
+use tokio_util::codec::FramedRead;
+
+use crate::linereader::{LineReader, MAX_LINE_LENGTH};
+use crate::queue::OutQueue;
+use crate::{Error, Result};
+
+// how often the writer retries lines held back by the flood limiter
+const QUEUE_POLL: Duration = Duration::from_millis(100);
+// how long a TCP connect plus TLS handshake may take before we move on
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Anything the bot can speak IRC over, TLS or plain TCP sockets.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+// connects to host:port, speaking TLS when a client config is given
+pub async fn connect(host: &str, tls: Option<Arc<ClientConfig>>) -> Result<Box<dyn Transport>> {
+    match timeout(CONNECT_TIMEOUT, open(host, tls)).await {
+        Ok(result) => result,
+        Err(_) => Err(Box::new(Error::new(&format!(
+            "timed out after {}s",
+            CONNECT_TIMEOUT.as_secs()
+        )))),
+    }
+}
+
+async fn open(host: &str, tls: Option<Arc<ClientConfig>>) -> Result<Box<dyn Transport>> {
+    let tcp_stream = TcpStream::connect(host).await?;
+
+    match tls {
+        Some(config) => {
+            let server_name = host.split(":").next().unwrap_or("").try_into()?;
+            let stream = TlsConnector::from(config).connect(server_name, tcp_stream).await?;
+            Ok(Box::new(stream))
+        }
+        None => Ok(Box::new(tcp_stream)),
+    }
+}
+
+// Forwards framed lines to the connection task until the server hangs up.
+pub async fn reader(read: ReadHalf<Box<dyn Transport>>, lines: mpsc::Sender<std::io::Result<String>>) {
+    let mut framed = FramedRead::new(read, LineReader::new(MAX_LINE_LENGTH));
+    while let Some(line) = framed.next().await {
+        let failed = line.is_err();
+        if lines.send(line).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Outgoing {
+    Line(String),
+    // write everything queued, ignoring the flood limiter
+    Flush(oneshot::Sender<()>),
+    // flush, then close the connection
+    Close(oneshot::Sender<()>),
+}
+
+async fn write_lines(write: &mut WriteHalf<Box<dyn Transport>>, lines: Vec<String>) -> Result<()> {
+    for line in lines {
+        // log::debug!("out: {:?}", line);
+        write.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    }
+    write.flush().await?;
+    Ok(())
+}
+
+// Owns the outbound queue, so every line goes through the flood limiter no
+// matter which task sent it.
+pub async fn writer(mut write: WriteHalf<Box<dyn Transport>>, mut outgoing: mpsc::UnboundedReceiver<Outgoing>) {
+    let mut queue = OutQueue::new();
+    loop {
+        let waiting = !queue.is_empty();
+        tokio::select! {
+            message = outgoing.recv() => match message {
+                Some(Outgoing::Line(line)) => queue.push(line),
+                Some(Outgoing::Flush(done)) => {
+                    if let Err(e) = write_lines(&mut write, queue.drain()).await {
+                        log::debug!("error flushing: {}", e);
+                    }
+                    let _ = done.send(());
+                }
+                Some(Outgoing::Close(done)) => {
+                    if let Err(e) = write_lines(&mut write, queue.drain()).await {
+                        log::debug!("error flushing: {}", e);
+                    }
+                    if let Err(e) = write.shutdown().await {
+                        log::debug!("error closing connection: {}", e);
+                    }
+                    let _ = done.send(());
+                    return;
+                }
+                None => return,
+            },
+            _ = sleep(QUEUE_POLL), if waiting => {}
+        }
+
+        let mut ready = Vec::new();
+        while let Some(line) = queue.next_ready() {
+            ready.push(line);
+        }
+        if ready.len() > 0 {
+            if let Err(e) = write_lines(&mut write, ready).await {
+                // the reader sees the same failure and ends the session
+                log::debug!("error writing: {}", e);
+            }
+        }
+    }
+}
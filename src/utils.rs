@@ -1,9 +1,152 @@
 extern crate reqwest;
 
-pub fn get_reqw_client() -> reqwest::blocking::Client {
-    let client = reqwest::blocking::Client::builder()
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+// * matches any run of characters, ? exactly one
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where the last * was and how much text it has swallowed so far
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// whether some text could match both patterns
+pub fn wildcard_overlap(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // reachable[i][j]: a[..i] and b[..j] can match the same text
+    let mut reachable = vec![vec![false; b.len() + 1]; a.len() + 1];
+    reachable[0][0] = true;
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            if !reachable[i][j] {
+                continue;
+            }
+            // a * matches nothing more, or one more character of the other side
+            if i < a.len() && a[i] == '*' {
+                reachable[i + 1][j] = true;
+                if j < b.len() {
+                    reachable[i][j + 1] = true;
+                }
+            }
+            if j < b.len() && b[j] == '*' {
+                reachable[i][j + 1] = true;
+                if i < a.len() {
+                    reachable[i + 1][j] = true;
+                }
+            }
+            if i < a.len() && j < b.len() && a[i] != '*' && b[j] != '*' && (a[i] == '?' || b[j] == '?' || a[i] == b[j]) {
+                reachable[i + 1][j + 1] = true;
+            }
+        }
+    }
+    reachable[a.len()][b.len()]
+}
+
+// "just now", "5m ago", "3h ago" or "2d ago"
+pub fn time_ago(then: DateTime<Utc>) -> String {
+    let elapsed = Utc::now().signed_duration_since(then);
+    if elapsed.num_minutes() < 1 {
+        return String::from("just now");
+    }
+    if elapsed.num_hours() < 1 {
+        return format!("{}m ago", elapsed.num_minutes());
+    }
+    if elapsed.num_days() < 1 {
+        return format!("{}h ago", elapsed.num_hours());
+    }
+    format!("{}d ago", elapsed.num_days())
+}
+
+// the timeout keeps a stalled request from outliving its worker job
+pub fn get_reqw_client(timeout: Duration) -> reqwest::Client {
+    let client = reqwest::Client::builder()
         .danger_accept_invalid_certs(true)
+        .timeout(timeout)
         .build()
         .unwrap();
     return client;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literals() {
+        assert!(wildcard_match("abc", "abc"));
+        assert!(!wildcard_match("abc", "abd"));
+        assert!(!wildcard_match("abc", "abcd"));
+        assert!(!wildcard_match("abcd", "abc"));
+        assert!(wildcard_match("", ""));
+        assert!(!wildcard_match("", "a"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_char() {
+        assert!(wildcard_match("a?c", "abc"));
+        assert!(wildcard_match("a?c", "aéc"));
+        assert!(!wildcard_match("a?c", "ac"));
+        assert!(!wildcard_match("a?c", "abbc"));
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("a*", "a"));
+        assert!(wildcard_match("*!*@*.example.org", "nick!user@host.example.org"));
+        assert!(!wildcard_match("*!*@*.example.org", "nick!user@example.org"));
+        assert!(wildcard_match("a**b", "ab"));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(wildcard_match("*abc", "ababc"));
+        assert!(wildcard_match("a*b*c", "aXbXbXc"));
+        assert!(wildcard_match("*a?c*", "xxabxabcxx"));
+        assert!(!wildcard_match("a*b*c", "aXbXbX"));
+        assert!(wildcard_match("*ab*ab", "abababab"));
+        assert!(!wildcard_match("*aab", "abab"));
+    }
+
+    #[test]
+    fn overlaps_literals_only_when_equal() {
+        assert!(wildcard_overlap("abc", "abc"));
+        assert!(!wildcard_overlap("abc", "abd"));
+        assert!(!wildcard_overlap("abc", "ab"));
+        assert!(wildcard_overlap("", ""));
+    }
+
+    #[test]
+    fn overlaps_through_wildcards() {
+        assert!(wildcard_overlap("*", ""));
+        assert!(wildcard_overlap("*", "anything"));
+        assert!(wildcard_overlap("a?c", "abc"));
+        assert!(wildcard_overlap("a*", "*b"));
+        assert!(wildcard_overlap("*x*", "?"));
+        assert!(!wildcard_overlap("a*", "b*"));
+        assert!(!wildcard_overlap("*a", "*b"));
+        assert!(!wildcard_overlap("??", "?"));
+    }
+}
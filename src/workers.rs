@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+use crate::config::ApiKeys;
+use crate::{say, IrcConnection, Result};
+
+// commands allowed in flight at once before new ones are turned away
+pub const DEFAULT_WORKERS: usize = 16;
+
+// A command that runs as its own task, returning the lines to say.
+pub type WorkerCommand = fn(job: Job) -> BoxFuture<'static, Result<Vec<String>>>;
+
+#[derive(Debug)]
+pub struct Job {
+    pub target: String,
+    pub rest: String,
+    // for HTTP clients, so requests give up before the job is written off
+    pub timeout: Duration,
+    pub api_keys: ApiKeys,
+}
+
+// Runs network-bound commands off the connection task, bounded so a flood of
+// requests can't pile up unanswered.
+#[derive(Debug)]
+pub struct WorkerPool {
+    permits: Arc<Semaphore>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> WorkerPool {
+        return WorkerPool {
+            permits: Arc::new(Semaphore::new(size.max(1))),
+        };
+    }
+
+    // false when too many commands are already running
+    pub fn submit(
+        &self,
+        name: &'static str,
+        handler: WorkerCommand,
+        stream: &IrcConnection,
+        job: Job,
+    ) -> bool {
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => return false,
+        };
+
+        let limit = job.timeout;
+        let target = job.target.clone();
+        let mut stream = stream.clone();
+
+        tokio::spawn(async move {
+            let result = timeout(limit, handler(job)).await;
+            drop(permit);
+
+            match result {
+                Ok(Ok(lines)) => {
+                    for line in lines {
+                        if let Err(e) = say(&mut stream, &target, &line) {
+                            log::debug!("dropping reply to !{}: {}", name, e);
+                        }
+                    }
+                }
+                Ok(Err(e)) => log::error!("command {} errored: {}", name, e),
+                Err(_) => {
+                    log::warn!("command {} timed out", name);
+                    let _ = say(&mut stream, &target, &format!("!{} timed out", name));
+                }
+            }
+        });
+        true
+    }
+}